@@ -0,0 +1,321 @@
+//! BMP reader and writer
+//!
+//! reader accepts BITMAPCOREHEADER, BITMAPINFOHEADER and V2 to V5 headers,
+//! 1, 4, 8-bit palette, 16, 24 and 32-bit pixels, BI_RGB, BI_BITFIELDS and BI_ALPHABITFIELDS.
+//! every image is converted to interleaved BGR whose rows are bottom-up, same layout as 24-bit BI_RGB,
+//! so top-down (negative height) files are flipped while reading.
+//! a palette image whose colors are all gray is read as one gray channel,
+//! and 16 or 32-bit pixels with an alpha mask keep alpha as a fourth channel.
+//! writer writes 24-bit BGR, 8-bit with a gray palette for a gray image,
+//! or 32-bit BGRA with BITMAPV5HEADER for an image with alpha, always 8 bits a sample.
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use yamakagashi_core::{Result, YamakagashiError};
+use crate::image::{alpha_of, to_bgr, Image, Pixels};
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+// BITMAPCOREHEADER, BITMAPINFOHEADER
+const CORE_HEADER_SIZE: u32 = 12;
+const INFO_HEADER_SIZE: u32 = 40;
+// BITMAPV5HEADER
+const V5_HEADER_SIZE: u32 = 124;
+
+// 'sRGB' of bV5CSType, LCS_GM_IMAGES of bV5Intent
+const LCS_SRGB: u32 = 0x7352_4742;
+const LCS_GM_IMAGES: u32 = 4;
+
+// red, green, blue, alpha masks of BI_RGB
+const RGB555_MASKS: [u32; 4] = [0x7C00, 0x03E0, 0x001F, 0];
+const RGB888_MASKS: [u32; 4] = [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0];
+// red, green, blue, alpha masks of 32-bit BGRA
+const BGRA8888_MASKS: [u32; 4] = [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000];
+
+pub fn bitmap_opener(input_path:&PathBuf) -> Result<Image> {
+
+    // file input
+    let file_size = std::fs::metadata(input_path)?.len();
+    let mut input_file = BufReader::new(File::open(input_path)?);
+
+    // bitmap file header
+    // signature check
+    let mut file_header = [0; 14];
+    input_file.read_exact(&mut file_header)?;
+    if &file_header[..2] != b"BM" {return Err(YamakagashiError::UnsupportedHeader("Not a BMP file".to_string()));}
+    let image_data_offset = u32::from_le_bytes([file_header[10], file_header[11], file_header[12], file_header[13]]);
+
+    // bitmap info header
+    let header_size = input_file.read_u32::<LittleEndian>()?;
+    let (width, height, bit_count, compression, clr_used, pels_per_meter) = match header_size {
+        CORE_HEADER_SIZE => {
+            let width = input_file.read_u16::<LittleEndian>()? as i32;
+            let height = input_file.read_u16::<LittleEndian>()? as i32;
+            let _planes = input_file.read_u16::<LittleEndian>()?;
+            let bit_count = input_file.read_u16::<LittleEndian>()?;
+            (width, height, bit_count, BI_RGB, 0, (0, 0))
+        },
+        _ if header_size >= INFO_HEADER_SIZE => {
+            let width = input_file.read_i32::<LittleEndian>()?;
+            let height = input_file.read_i32::<LittleEndian>()?;
+            let _planes = input_file.read_u16::<LittleEndian>()?;
+            let bit_count = input_file.read_u16::<LittleEndian>()?;
+            let compression = input_file.read_u32::<LittleEndian>()?;
+            let _size_image = input_file.read_u32::<LittleEndian>()?;
+            let x_pels_per_meter = input_file.read_i32::<LittleEndian>()?;
+            let y_pels_per_meter = input_file.read_i32::<LittleEndian>()?;
+            let clr_used = input_file.read_u32::<LittleEndian>()?;
+            let _clr_important = input_file.read_u32::<LittleEndian>()?;
+            (width, height, bit_count, compression, clr_used, (x_pels_per_meter, y_pels_per_meter))
+        },
+        _ => return Err(YamakagashiError::UnsupportedHeader(format!("BMP header size {header_size} is not supported"))),
+    };
+
+    if width <= 0 {return Err(YamakagashiError::UnsupportedHeader("Only positive width size BMP files are supported".to_string()));}
+    if height == 0 {return Err(YamakagashiError::UnsupportedHeader("BMP height is 0".to_string()));}
+    if ![1, 4, 8, 16, 24, 32].contains(&bit_count) {return Err(YamakagashiError::UnsupportedHeader(format!("{bit_count}-bit BMP files are not supported")));}
+
+    // masks live in V2 or later header, or just after BITMAPINFOHEADER
+    let masks = match compression {
+        BI_RGB if bit_count == 16 => RGB555_MASKS,
+        BI_RGB => RGB888_MASKS,
+        BI_BITFIELDS | BI_ALPHABITFIELDS if bit_count == 16 || bit_count == 32 => {
+            let mask_count = if header_size == INFO_HEADER_SIZE && compression == BI_BITFIELDS { 3 } else { 4 };
+            let mut masks = [0; 4];
+            for mask in masks.iter_mut().take(mask_count) {
+                *mask = input_file.read_u32::<LittleEndian>()?;
+            }
+            if header_size > INFO_HEADER_SIZE && header_size < 56 { masks[3] = 0; } // BITMAPV2INFOHEADER has no alpha mask
+            masks
+        },
+        _ => return Err(YamakagashiError::UnsupportedHeader(format!("BMP compression {compression} with {bit_count}-bit is not supported"))),
+    };
+
+    // color table follows the header (and masks of BITMAPINFOHEADER)
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    if bit_count <= 8 {
+        let masks_size = if header_size == INFO_HEADER_SIZE { match compression { BI_BITFIELDS => 12, BI_ALPHABITFIELDS => 16, _ => 0 } } else { 0 };
+        input_file.seek(SeekFrom::Start(file_header.len() as u64 + header_size as u64 + masks_size))?;
+        let entry_count = if clr_used == 0 || clr_used > 1 << bit_count { 1 << bit_count } else { clr_used };
+        let entry_size = if header_size == CORE_HEADER_SIZE { 3 } else { 4 };
+        for _ in 0..entry_count {
+            let mut entry = [0u8; 4];
+            input_file.read_exact(&mut entry[..entry_size])?;
+            palette.push([entry[0], entry[1], entry[2]]);
+        }
+    }
+
+    let width = width as u32;
+    let top_down = height < 0;
+    let height = height.unsigned_abs();
+
+    // refuse a header asking for more rows than the file has, before allocating
+    let row_size = (bit_count as u64 * width as u64).div_ceil(32) * 4;
+    let needed = image_data_offset as u64 + row_size * height as u64;
+    if file_size < needed {
+        return Err(YamakagashiError::TruncatedStream { needed: needed as usize, available: file_size as usize });
+    }
+
+    // gray palette keeps a single channel, alpha mask adds a fourth one
+    let number_of_colors: u8 = match bit_count {
+        1 | 4 | 8 if palette.iter().all(|&[b, g, r]| b == g && g == r) => 1,
+        16 | 32 if masks[3] != 0 => 4,
+        _ => 3,
+    };
+    let pixel_row_size = width as usize * number_of_colors as usize;
+    let mut pixel_data = vec![0u8; pixel_row_size * height as usize];
+
+    input_file.seek(SeekFrom::Start(image_data_offset as u64))?;
+    let mut row = vec![0u8; row_size as usize];
+    for i in 0..height as usize {
+        input_file.read_exact(&mut row)?;
+
+        // internal buffer is bottom-up
+        let y = if top_down { height as usize - 1 - i } else { i };
+        let pixel_row = &mut pixel_data[y * pixel_row_size..(y + 1) * pixel_row_size];
+
+        match bit_count {
+            24 => pixel_row.copy_from_slice(&row[..pixel_row_size]),
+            16 | 32 => {
+                let bytes = bit_count as usize / 8;
+                for (pixel, bgra) in row.chunks_exact(bytes).zip(pixel_row.chunks_exact_mut(number_of_colors as usize)) {
+                    let value = pixel.iter().rev().fold(0u32, |acc, &byte| acc << 8 | byte as u32);
+                    bgra[0] = masked_channel(value, masks[2]);
+                    bgra[1] = masked_channel(value, masks[1]);
+                    bgra[2] = masked_channel(value, masks[0]);
+                    if let Some(alpha) = bgra.get_mut(3) { *alpha = masked_channel(value, masks[3]); }
+                }
+            },
+            _ => {
+                let pixels_per_byte = 8 / bit_count as usize;
+                for (x, pixel) in pixel_row.chunks_exact_mut(number_of_colors as usize).enumerate() {
+                    let shift = 8 - bit_count as usize * (x % pixels_per_byte + 1);
+                    let index = (row[x / pixels_per_byte] >> shift) as usize & ((1 << bit_count) - 1);
+                    let entry = palette.get(index).ok_or_else(|| YamakagashiError::UnsupportedHeader(format!("palette index {index} is out of {} colors", palette.len())))?;
+                    pixel.copy_from_slice(&entry[..number_of_colors as usize]);
+                }
+            },
+        }
+    }
+
+    Ok(Image { size: (width, height), number_of_colors, pixels: Pixels::Eight(pixel_data), top_down, pels_per_meter })
+}
+
+// pick bits of mask and scale them to 0..=255
+fn masked_channel(value: u32, mask: u32) -> u8 {
+
+    if mask == 0 { return 0; }
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    let channel = ((value & mask) >> mask.trailing_zeros()) as u64;
+
+    ((channel * 255 + max / 2) / max) as u8
+}
+
+// write as 24-bit BMP, 8-bit gray palette BMP, or 32-bit BGRA V5 BMP, top-down if the source was
+// 16-bit samples are cut to 8-bit
+pub fn bitmap_writer(output_path:&PathBuf, image:&Image) -> Result<()> {
+
+    // gray and alpha is widened to BGRA, BMP has no gray with alpha
+    let (header_size, bit_count, palette_size) = match image.number_of_colors {
+        1 => (INFO_HEADER_SIZE, 8u16, 256 * 4),
+        2 | 4 => (V5_HEADER_SIZE, 32u16, 0),
+        _ => (INFO_HEADER_SIZE, 24u16, 0),
+    };
+
+    let image_size = image.size;
+    let bytes_per_pixel = bit_count as u32 / 8;
+    let row_size = (bytes_per_pixel * image_size.0 + 3) & !3; // 各行は4バイトの倍数にパディング
+    let image_size_with_padding = row_size * image_size.1;
+
+    // edit header
+    let signature = b"BM";
+    let file_size = 14 + header_size + palette_size + image_size_with_padding;
+    let booking_space = &[0, 0, 0, 0];
+    let image_data_offset: u32 = 14 + header_size + palette_size;
+
+    let width = image_size.0;
+    let height = if image.top_down { -(image_size.1 as i32) } else { image_size.1 as i32 };
+    let planes = 1u16;
+
+    let compression = if header_size == V5_HEADER_SIZE { BI_BITFIELDS } else { BI_RGB };
+    let size_image = image_size_with_padding;
+    let (x_pels_per_meter, y_pels_per_meter) = image.pels_per_meter;
+    let clr_used = palette_size / 4;
+    let clr_important = 0u32;
+
+    // file output
+    let mut output_file = BufWriter::new(File::create(output_path)?);
+
+    output_file.write_all(signature)?;
+    output_file.write_u32::<LittleEndian>(file_size)?;
+    output_file.write_all(booking_space)?;
+    output_file.write_u32::<LittleEndian>(image_data_offset)?;
+    output_file.write_u32::<LittleEndian>(header_size)?;
+    output_file.write_u32::<LittleEndian>(width)?;
+    output_file.write_i32::<LittleEndian>(height)?;
+    output_file.write_u16::<LittleEndian>(planes)?;
+    output_file.write_u16::<LittleEndian>(bit_count)?;
+
+    output_file.write_u32::<LittleEndian>(compression)?;
+    output_file.write_u32::<LittleEndian>(size_image)?;
+    output_file.write_i32::<LittleEndian>(x_pels_per_meter)?;
+    output_file.write_i32::<LittleEndian>(y_pels_per_meter)?;
+    output_file.write_u32::<LittleEndian>(clr_used)?;
+    output_file.write_u32::<LittleEndian>(clr_important)?;
+
+    if header_size == V5_HEADER_SIZE {
+        for mask in BGRA8888_MASKS {
+            output_file.write_u32::<LittleEndian>(mask)?;
+        }
+        output_file.write_u32::<LittleEndian>(LCS_SRGB)?;
+        output_file.write_all(&[0; 36 + 12])?; // endpoints and gamma, unused by sRGB
+        output_file.write_u32::<LittleEndian>(LCS_GM_IMAGES)?;
+        output_file.write_all(&[0; 12])?; // profile data, profile size, reserved
+    }
+
+    for gray in 0..clr_used {
+        output_file.write_all(&[gray as u8, gray as u8, gray as u8, 0])?;
+    }
+
+    let row_padding = &vec![0; (row_size - width*bytes_per_pixel) as usize];
+    let pixels = image.pixels.to_eight();
+    let rows = pixels.chunks(image.number_of_colors as usize * width as usize);
+    let rows: Box<dyn Iterator<Item = &[u8]>> = if image.top_down { Box::new(rows.rev()) } else { Box::new(rows) };
+    for row in rows {
+        match image.number_of_colors {
+            2 => for pixel in row.chunks(2) {
+                let [b, g, r] = to_bgr(pixel);
+                output_file.write_all(&[b, g, r, alpha_of(pixel)])?;
+            },
+            _ => output_file.write_all(row)?,
+        }
+        output_file.write_all(row_padding)?;
+    }
+
+    output_file.flush()?;
+
+    Ok(())
+}
+
+#[test]
+fn bitmap_test() {
+    let path = std::env::temp_dir().join(format!("yamakagashi_bitmap_test_{}.bmp", std::process::id()));
+
+    // gray goes through the gray palette, BGR as 24-bit, BGRA as V5 bitfields, widths with row padding,
+    // rows stay bottom-up in memory whichever way the file stores them, and the resolution comes back
+    for (number_of_colors, top_down) in [1u8, 3, 4].into_iter().flat_map(|number_of_colors| [(number_of_colors, false), (number_of_colors, true)]) {
+        let size = (5, 3);
+        let pixels: Vec<u8> = (0..size.0 * size.1 * number_of_colors as u32).map(|i| (i * 37 % 256) as u8).collect();
+        let image = Image { size, number_of_colors, pixels: Pixels::Eight(pixels.clone()), top_down, pels_per_meter: (2835, 3780) };
+        bitmap_writer(&path, &image).unwrap();
+        let read = bitmap_opener(&path).unwrap();
+        assert_eq!((read.size, read.number_of_colors, read.top_down, read.pels_per_meter), (size, number_of_colors, top_down, (2835, 3780)));
+        assert!(matches!(read.pixels, Pixels::Eight(read) if read == pixels), "{number_of_colors} colors, top-down {top_down}");
+    }
+
+    // BITMAPCOREHEADER 2x1 1-bit with a black and white palette of 3-byte entries
+    let core: Vec<u8> = [&b"BM"[..], &[36, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0], &[12, 0, 0, 0, 2, 0, 1, 0, 1, 0, 1, 0], &[0, 0, 0, 255, 255, 255], &[0x40, 0, 0, 0]].concat();
+    std::fs::write(&path, core).unwrap();
+    let read = bitmap_opener(&path).unwrap();
+    assert!(matches!(read.pixels, Pixels::Eight(read) if read == [0, 255]));
+
+    // 1x2 top-down 16-bit BI_BITFIELDS of RGB565 masks after BITMAPINFOHEADER, red over blue
+    let info = |height: i32| -> Vec<u8> { [&40u32.to_le_bytes()[..], &1i32.to_le_bytes(), &height.to_le_bytes(), &[1, 0, 16, 0], &BI_BITFIELDS.to_le_bytes(), &[0; 20]].concat() };
+    let bitfields: Vec<u8> = [&b"BM"[..], &[74, 0, 0, 0, 0, 0, 0, 0, 66, 0, 0, 0], &info(-2), &[0x00, 0xF8, 0, 0, 0xE0, 0x07, 0, 0, 0x1F, 0, 0, 0], &[0x00, 0xF8, 0, 0, 0x1F, 0x00, 0, 0]].concat();
+    std::fs::write(&path, bitfields).unwrap();
+    let read = bitmap_opener(&path).unwrap();
+    assert!(read.top_down);
+    assert!(matches!(read.pixels, Pixels::Eight(read) if read == [255, 0, 0, 0, 0, 255]));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn malformed_bitmap_test() {
+    let path = std::env::temp_dir().join(format!("yamakagashi_malformed_bitmap_test_{}.bmp", std::process::id()));
+    let image = Image { size: (4, 4), number_of_colors: 3, pixels: Pixels::Eight(vec![128; 48]), top_down: false, pels_per_meter: (0, 0) };
+    bitmap_writer(&path, &image).unwrap();
+    let file = std::fs::read(&path).unwrap();
+
+    let with = |at: usize, bytes: &[u8]| { let mut file = file.clone(); file[at..at + bytes.len()].copy_from_slice(bytes); file };
+    let cases: [(Vec<u8>, &str); 6] = [
+        (with(0, b"MB"), "signature"),
+        (with(14, &20u32.to_le_bytes()), "header size"),
+        (with(18, &(-4i32).to_le_bytes()), "negative width"),
+        (with(22, &0i32.to_le_bytes()), "zero height"),
+        (with(28, &[2, 0]), "bit count"),
+        (with(30, &1u32.to_le_bytes()), "RLE8 compression"),
+    ];
+    for (case, name) in cases {
+        std::fs::write(&path, case).unwrap();
+        assert!(matches!(bitmap_opener(&path), Err(YamakagashiError::UnsupportedHeader(_))), "{name}");
+    }
+    // more rows than the file has
+    std::fs::write(&path, with(22, &400i32.to_le_bytes())).unwrap();
+    assert!(matches!(bitmap_opener(&path), Err(YamakagashiError::TruncatedStream { .. })));
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,495 @@
+//! .yama container
+//!
+//! version 01: signature, version, header, image chunk
+//! version 02: same as 01 and crc32 footer of header and image chunk
+//! version 03: signature, version, then typed chunks like PNG
+//!
+//! a chunk of version 03 is
+//!     length u32, chunk type 4 bytes, data (length bytes), crc32 of chunk type and data
+//! upper case first letter of chunk type means critical, a reader has to understand it,
+//! lower case first letter means ancillary, a reader which doesn't know it skips it.
+//!
+//! YHDR  width u32, height u32, number of colors u8, bit depth u8 (8, 16, or 32 for float),
+//!       color transform u8 (0 none, 1 YCoCg-R, 2 YCbCr), chroma subsampling u8 (0 4:4:4, 1 4:2:2, 2 4:2:0),
+//!       scan direction u8 (0 rows, 1 columns), entropy backend u8 (0 xz, 1 zstd, 2 brotli, 3 none, 4 range), must be the first chunk
+//! YDAT  image data, several YDAT are concatenated in order
+//! yTXT  keyword, 0, text (utf-8), any number of them
+//! yPHY  x pixels per meter i32, y pixels per meter i32
+//! yORI  1 if rows of the source were top-down, 0 if bottom-up
+//! YALP  empty, alpha (the last plane) follows the coded planes in the image data as raw bytes
+//! YSEG  empty, planes of full resolution share unit sizes written once ahead of them
+//! YROW  empty, rows may take unit sizes and coeffs from the row above, their row modes lead the image data
+//! YPAT  empty, planes are quad-trees of 2-D patches and rows of units
+//! YCNT  empty, a unit stores the count of its coeffs up to the last non-zero one and only those,
+//!       without it a unit stores a coeff for every sample
+//! YVAR  empty, unit sizes and coeff counts are varints of 7 bits a byte from the top instead of u16, units may run over 65535 samples
+//! YTIL  tile width u32, tile height u32, then scan direction u8 and bytes u32 of each tile,
+//!       image data is the tiles one after another, each packed on its own by the entropy backend, the scan direction of YHDR is unused
+//! YEND  empty, must be the last chunk
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use yamakagashi_core::{ChromaSubsampling, CodingOptions, ColorTransform, Entropy, Result, ScanDirection, YamakagashiError};
+
+const SIGNATURE: &[u8; 4] = b"YAMA";
+// signature 4, version 2
+const SIGNATURE_SIZE: usize = 6;
+// signature 4, version 2, width 4, height 4, number of colors 1, chunk size 4
+const HEADER_SIZE: usize = 19;
+// crc32 of header and chunk, since version 02
+const FOOTER_SIZE: usize = 4;
+// length 4, chunk type 4, crc32 4
+const CHUNK_OVERHEAD: usize = 12;
+
+const YHDR: [u8; 4] = *b"YHDR";
+const YDAT: [u8; 4] = *b"YDAT";
+const YTXT: [u8; 4] = *b"yTXT";
+const YPHY: [u8; 4] = *b"yPHY";
+const YORI: [u8; 4] = *b"yORI";
+const YALP: [u8; 4] = *b"YALP";
+const YSEG: [u8; 4] = *b"YSEG";
+const YROW: [u8; 4] = *b"YROW";
+const YPAT: [u8; 4] = *b"YPAT";
+const YCNT: [u8; 4] = *b"YCNT";
+const YVAR: [u8; 4] = *b"YVAR";
+const YTIL: [u8; 4] = *b"YTIL";
+const YEND: [u8; 4] = *b"YEND";
+
+// (image size, number of colors, bit depth, color transform, subsampling, scan direction, entropy backend) of YHDR
+type Header = ((u32, u32), u8, u8, ColorTransform, ChromaSubsampling, ScanDirection, Entropy);
+
+// tile width 4, tile height 4
+const TILE_INDEX_HEADER_SIZE: usize = 8;
+// scan direction 1, bytes 4
+const TILE_ENTRY_SIZE: usize = 5;
+
+// YDAT bigger than this is split into several chunks
+const MAX_YDAT_SIZE: usize = 1 << 20;
+
+pub struct YamakagashiFile {
+    pub image_size: (u32, u32),
+    pub number_of_colors: u8,
+    // bits of a sample, 8 or 16, 32 is float
+    pub bit_depth: u8,
+    pub image_data: Vec<u8>,
+    // (keyword, text) of yTXT chunks
+    pub text: Vec<(String, String)>,
+    pub top_down: bool,
+    // (x, y), 0 is unknown
+    pub pels_per_meter: (i32, i32),
+    // coding tools the image data is coded with
+    pub coding: CodingOptions,
+    // None is one stream for the whole image
+    pub tile_index: Option<TileIndex>,
+}
+
+// how image data is cut into tiles
+pub struct TileIndex {
+    pub tile_size: (u32, u32),
+    // (scan direction, bytes) of each tile in the order of image data
+    pub tiles: Vec<(ScanDirection, usize)>,
+}
+
+// write version 03 container
+pub fn yamakagashi_writer(output_path:&PathBuf, yamakagashi:&YamakagashiFile) -> Result<()> {
+
+    // file output
+    let mut output_file = BufWriter::new(File::create(output_path)?);
+    write_container(&mut output_file, yamakagashi)?;
+    output_file.flush()?;
+
+    Ok(())
+}
+
+fn write_container<W: Write>(output_file:&mut W, yamakagashi:&YamakagashiFile) -> Result<()> {
+
+    output_file.write_all(SIGNATURE)?;
+    output_file.write_all(b"03")?;
+
+    // edit header
+    let mut header = Vec::with_capacity(14);
+    header.write_u32::<BigEndian>(yamakagashi.image_size.0)?;
+    header.write_u32::<BigEndian>(yamakagashi.image_size.1)?;
+    header.write_u8(yamakagashi.number_of_colors)?;
+    header.write_u8(yamakagashi.bit_depth)?;
+    header.write_u8(yamakagashi.coding.color_transform.id())?;
+    header.write_u8(yamakagashi.coding.subsampling.id())?;
+    header.write_u8(yamakagashi.coding.scan.id())?;
+    header.write_u8(yamakagashi.coding.entropy.id())?;
+    write_chunk(output_file, YHDR, &header)?;
+
+    for (keyword, text) in &yamakagashi.text {
+        write_chunk(output_file, YTXT, &[keyword.as_bytes(), &[0], text.as_bytes()].concat())?;
+    }
+
+    if yamakagashi.pels_per_meter != (0, 0) {
+        let mut physical = Vec::with_capacity(8);
+        physical.write_i32::<BigEndian>(yamakagashi.pels_per_meter.0)?;
+        physical.write_i32::<BigEndian>(yamakagashi.pels_per_meter.1)?;
+        write_chunk(output_file, YPHY, &physical)?;
+    }
+
+    write_chunk(output_file, YORI, &[yamakagashi.top_down as u8])?;
+
+    if yamakagashi.coding.lossless_alpha {
+        write_chunk(output_file, YALP, &[])?;
+    }
+
+    if yamakagashi.coding.shared_units {
+        write_chunk(output_file, YSEG, &[])?;
+    }
+
+    if yamakagashi.coding.inter_row {
+        write_chunk(output_file, YROW, &[])?;
+    }
+
+    if yamakagashi.coding.patches {
+        write_chunk(output_file, YPAT, &[])?;
+    }
+
+    if !yamakagashi.coding.trailing_zeros {
+        write_chunk(output_file, YCNT, &[])?;
+    }
+
+    if yamakagashi.coding.varint_sizes {
+        write_chunk(output_file, YVAR, &[])?;
+    }
+
+    if let Some(tile_index) = &yamakagashi.tile_index {
+        let mut index = Vec::with_capacity(TILE_INDEX_HEADER_SIZE + TILE_ENTRY_SIZE * tile_index.tiles.len());
+        index.write_u32::<BigEndian>(tile_index.tile_size.0)?;
+        index.write_u32::<BigEndian>(tile_index.tile_size.1)?;
+        for &(scan, bytes) in &tile_index.tiles {
+            index.write_u8(scan.id())?;
+            let bytes = u32::try_from(bytes).map_err(|_| YamakagashiError::UnsupportedHeader(format!("tile of {bytes} bytes is too big for the tile index")))?;
+            index.write_u32::<BigEndian>(bytes)?;
+        }
+        write_chunk(output_file, YTIL, &index)?;
+    }
+
+    for image_data in yamakagashi.image_data.chunks(MAX_YDAT_SIZE) {
+        write_chunk(output_file, YDAT, image_data)?;
+    }
+
+    write_chunk(output_file, YEND, &[])?;
+
+    Ok(())
+}
+
+fn write_chunk<W: Write>(output_file:&mut W, chunk_type:[u8; 4], data:&[u8]) -> Result<()> {
+
+    let length = u32::try_from(data.len()).map_err(|_| YamakagashiError::UnsupportedHeader(format!("chunk {} of {} bytes is too big", String::from_utf8_lossy(&chunk_type), data.len())))?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&chunk_type);
+    crc.update(data);
+
+    output_file.write_u32::<BigEndian>(length)?;
+    output_file.write_all(&chunk_type)?;
+    output_file.write_all(data)?;
+    output_file.write_u32::<BigEndian>(crc.finalize())?;
+
+    Ok(())
+}
+
+// verify: check crc, pass false to open a damaged file anyway
+pub fn yamakagashi_opener(input_path:&PathBuf, verify:bool) -> Result<YamakagashiFile> {
+
+    // file input
+    let mut file_bytes = Vec::new();
+    File::open(input_path)?.read_to_end(&mut file_bytes)?;
+    read_container(&file_bytes, verify)
+}
+
+fn read_container(file_bytes:&[u8], verify:bool) -> Result<YamakagashiFile> {
+
+    if file_bytes.len() < SIGNATURE_SIZE {
+        return Err(YamakagashiError::TruncatedStream { needed: SIGNATURE_SIZE, available: file_bytes.len() });
+    }
+
+    // signature check
+    if &file_bytes[..4] != SIGNATURE {return Err(YamakagashiError::UnsupportedHeader("Not a YAMAKAGASHI file".to_string()));}
+
+    // version check, 01 has no footer, 02 ends with crc32 of header and chunk, 03 is chunked
+    match &file_bytes[4..SIGNATURE_SIZE] {
+        b"01" => legacy_opener(file_bytes, 0, verify),
+        b"02" => legacy_opener(file_bytes, FOOTER_SIZE, verify),
+        b"03" => chunked_opener(file_bytes, verify),
+        virsion => Err(YamakagashiError::UnsupportedHeader(format!("unknown version {:?}", String::from_utf8_lossy(virsion)))),
+    }
+}
+
+fn legacy_opener(file_bytes:&[u8], footer_size:usize, verify:bool) -> Result<YamakagashiFile> {
+
+    if file_bytes.len() < HEADER_SIZE {
+        return Err(YamakagashiError::TruncatedStream { needed: HEADER_SIZE, available: file_bytes.len() });
+    }
+    let mut input_file = &file_bytes[SIGNATURE_SIZE..];
+
+    let width = input_file.read_u32::<BigEndian>()?;
+    let height = input_file.read_u32::<BigEndian>()?;
+    let number_of_colors = input_file.read_u8()?;
+    let chunk_size = input_file.read_u32::<BigEndian>()? as usize;
+
+    // read exactly chunk size, and footer after it
+    let needed = HEADER_SIZE + chunk_size + footer_size;
+    if file_bytes.len() < needed {
+        return Err(YamakagashiError::TruncatedStream { needed, available: file_bytes.len() });
+    }
+    let (yamakagashi_image_data, mut footer) = input_file.split_at(chunk_size);
+
+    if footer_size == FOOTER_SIZE {
+        let expected = footer.read_u32::<BigEndian>()?;
+        let actual = crc32fast::hash(&file_bytes[..HEADER_SIZE + chunk_size]);
+        if verify && expected != actual {return Err(YamakagashiError::Corrupted { expected, actual });}
+    }
+
+    // nothing is defined after the footer
+    if !footer.is_empty() {
+        return Err(YamakagashiError::TrailingData { remaining: footer.len() });
+    }
+
+    // version 01 and 02 have no coeff counts, a unit stores a coeff for every sample
+    let coding = CodingOptions { trailing_zeros: true, ..Default::default() };
+
+    Ok(YamakagashiFile { image_size: (width, height), number_of_colors, bit_depth: 8, image_data: yamakagashi_image_data.to_vec(), text: Vec::new(), top_down: false, pels_per_meter: (0, 0), coding, tile_index: None })
+}
+
+fn chunked_opener(file_bytes:&[u8], verify:bool) -> Result<YamakagashiFile> {
+
+    let mut input_file = &file_bytes[SIGNATURE_SIZE..];
+
+    let mut header: Option<Header> = None;
+    let mut image_data = Vec::new();
+    let mut text = Vec::new();
+    let mut top_down = false;
+    let mut pels_per_meter = (0, 0);
+    let mut lossless_alpha = false;
+    let mut shared_units = false;
+    let mut inter_row = false;
+    let mut patches = false;
+    let mut counts = false;
+    let mut varint_sizes = false;
+    let mut tile_index = None;
+
+    loop {
+        let (chunk_type, mut data) = read_chunk(&mut input_file, file_bytes.len(), verify)?;
+
+        if header.is_none() && chunk_type != YHDR {
+            return Err(YamakagashiError::UnsupportedHeader("YHDR has to be the first chunk".to_string()));
+        }
+
+        match chunk_type {
+            YHDR => {
+                if header.is_some() {return Err(YamakagashiError::UnsupportedHeader("YHDR appears twice".to_string()));}
+                if data.len() != 14 {return Err(YamakagashiError::UnsupportedHeader(format!("YHDR has {} bytes instead of 14", data.len())));}
+                let width = data.read_u32::<BigEndian>()?;
+                let height = data.read_u32::<BigEndian>()?;
+                let number_of_colors = data.read_u8()?;
+                let bit_depth = data.read_u8()?;
+                let color_transform = ColorTransform::from_id(data.read_u8()?)?;
+                let subsampling = ChromaSubsampling::from_id(data.read_u8()?)?;
+                let scan = ScanDirection::from_id(data.read_u8()?)?;
+                let entropy = Entropy::from_id(data.read_u8()?)?;
+                header = Some(((width, height), number_of_colors, bit_depth, color_transform, subsampling, scan, entropy));
+            },
+            YDAT => image_data.extend_from_slice(data),
+            YTXT => {
+                let separator = data.iter().position(|&byte| byte == 0)
+                    .ok_or_else(|| YamakagashiError::UnsupportedHeader("yTXT has no keyword separator".to_string()))?;
+                text.push((String::from_utf8_lossy(&data[..separator]).into_owned(), String::from_utf8_lossy(&data[separator+1..]).into_owned()));
+            },
+            YPHY => {
+                if data.len() != 8 {return Err(YamakagashiError::UnsupportedHeader(format!("yPHY has {} bytes instead of 8", data.len())));}
+                pels_per_meter = (data.read_i32::<BigEndian>()?, data.read_i32::<BigEndian>()?);
+            },
+            YORI => {
+                if data.len() != 1 {return Err(YamakagashiError::UnsupportedHeader(format!("yORI has {} bytes instead of 1", data.len())));}
+                top_down = data[0] != 0;
+            },
+            YALP => {
+                if !data.is_empty() {return Err(YamakagashiError::UnsupportedHeader(format!("YALP has {} bytes instead of 0", data.len())));}
+                lossless_alpha = true;
+            },
+            YSEG => {
+                if !data.is_empty() {return Err(YamakagashiError::UnsupportedHeader(format!("YSEG has {} bytes instead of 0", data.len())));}
+                shared_units = true;
+            },
+            YROW => {
+                if !data.is_empty() {return Err(YamakagashiError::UnsupportedHeader(format!("YROW has {} bytes instead of 0", data.len())));}
+                inter_row = true;
+            },
+            YPAT => {
+                if !data.is_empty() {return Err(YamakagashiError::UnsupportedHeader(format!("YPAT has {} bytes instead of 0", data.len())));}
+                patches = true;
+            },
+            YCNT => {
+                if !data.is_empty() {return Err(YamakagashiError::UnsupportedHeader(format!("YCNT has {} bytes instead of 0", data.len())));}
+                counts = true;
+            },
+            YVAR => {
+                if !data.is_empty() {return Err(YamakagashiError::UnsupportedHeader(format!("YVAR has {} bytes instead of 0", data.len())));}
+                varint_sizes = true;
+            },
+            YTIL => {
+                if tile_index.is_some() {return Err(YamakagashiError::UnsupportedHeader("YTIL appears twice".to_string()));}
+                if data.len() < TILE_INDEX_HEADER_SIZE || !(data.len() - TILE_INDEX_HEADER_SIZE).is_multiple_of(TILE_ENTRY_SIZE) {
+                    return Err(YamakagashiError::UnsupportedHeader(format!("YTIL has {} bytes, not a whole number of tiles", data.len())));
+                }
+                let tile_size = (data.read_u32::<BigEndian>()?, data.read_u32::<BigEndian>()?);
+                let tiles = data.chunks_exact(TILE_ENTRY_SIZE).map(|mut entry| Ok((ScanDirection::from_id(entry.read_u8()?)?, entry.read_u32::<BigEndian>()? as usize))).collect::<Result<_>>()?;
+                tile_index = Some(TileIndex { tile_size, tiles });
+            },
+            YEND => break,
+            // ancillary chunks this version doesn't know are skipped
+            _ if chunk_type[0].is_ascii_lowercase() => continue,
+            _ => return Err(YamakagashiError::UnsupportedHeader(format!("unknown critical chunk {:?}", String::from_utf8_lossy(&chunk_type)))),
+        }
+    }
+
+    // nothing is defined after YEND
+    if !input_file.is_empty() {
+        return Err(YamakagashiError::TrailingData { remaining: input_file.len() });
+    }
+
+    let (image_size, number_of_colors, bit_depth, color_transform, subsampling, scan, entropy) = header.ok_or_else(|| YamakagashiError::UnsupportedHeader("YHDR is missing".to_string()))?;
+
+    let coding = CodingOptions { lossless_alpha, color_transform, subsampling, shared_units, inter_row, patches, scan, entropy, trailing_zeros: !counts, varint_sizes };
+
+    // tiles have to cover image data exactly
+    if let Some(TileIndex { tiles, .. }) = &tile_index {
+        let tiles_size = tiles.iter().map(|&(_, bytes)| bytes).sum::<usize>();
+        if tiles_size > image_data.len() {
+            return Err(YamakagashiError::TruncatedStream { needed: tiles_size, available: image_data.len() });
+        }
+        if tiles_size < image_data.len() {
+            return Err(YamakagashiError::TrailingData { remaining: image_data.len() - tiles_size });
+        }
+    }
+
+    Ok(YamakagashiFile { image_size, number_of_colors, bit_depth, image_data, text, top_down, pels_per_meter, coding, tile_index })
+}
+
+// read one chunk and move input forward, file_size is only for error report
+fn read_chunk<'a>(input_file:&mut &'a [u8], file_size:usize, verify:bool) -> Result<([u8; 4], &'a [u8])> {
+
+    let position = file_size - input_file.len();
+    if input_file.len() < CHUNK_OVERHEAD {
+        return Err(YamakagashiError::TruncatedStream { needed: position + CHUNK_OVERHEAD, available: file_size });
+    }
+
+    let length = input_file.read_u32::<BigEndian>()? as usize;
+    let mut chunk_type = [0; 4];
+    input_file.read_exact(&mut chunk_type)?;
+    if input_file.len() < length + 4 {
+        return Err(YamakagashiError::TruncatedStream { needed: position + CHUNK_OVERHEAD + length, available: file_size });
+    }
+    let (data, mut rest) = input_file.split_at(length);
+
+    let expected = rest.read_u32::<BigEndian>()?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&chunk_type);
+    crc.update(data);
+    let actual = crc.finalize();
+    if verify && expected != actual {return Err(YamakagashiError::Corrupted { expected, actual });}
+
+    *input_file = rest;
+
+    Ok((chunk_type, data))
+}
+
+#[test]
+fn legacy_test() {
+    // 16x8 BGR encoded at quality 50 by the versions writing 01 and 02, and the samples they decode it to
+    let expected = include_bytes!("../testdata/legacy.bgr");
+    for name in ["legacy_v01.yama", "legacy_v02.yama"] {
+        let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata")).join(name);
+        let file = yamakagashi_opener(&path, true).unwrap();
+        assert_eq!((file.image_size, file.number_of_colors, file.bit_depth), ((16, 8), 3, 8));
+        let samples = yamakagashi_core::yamakagashi_to_bitmap::<u8>(file.image_data, file.number_of_colors, file.image_size, &file.coding).unwrap();
+        assert_eq!(samples, expected, "{name}");
+    }
+}
+#[test]
+fn footer_test() {
+    // 2x1 gray 02 file of 3 bytes of image data, the footer is crc32 of everything before it
+    let body: Vec<u8> = [&b"YAMA02"[..], &[0, 0, 0, 2, 0, 0, 0, 1, 1, 0, 0, 0, 3], &[7, 8, 9]].concat();
+    let file = [body.clone(), crc32fast::hash(&body).to_be_bytes().to_vec()].concat();
+    let opened = read_container(&file, true).unwrap();
+    assert_eq!((opened.image_size, opened.number_of_colors, opened.image_data), ((2, 1), 1, vec![7, 8, 9]));
+    assert!(opened.coding.trailing_zeros);
+
+    // a flipped byte of image data is caught unless verify is off
+    let mut damaged = file.clone();
+    damaged[HEADER_SIZE] ^= 1;
+    assert!(matches!(read_container(&damaged, true), Err(YamakagashiError::Corrupted { .. })));
+    assert_eq!(read_container(&damaged, false).unwrap().image_data, vec![6, 8, 9]);
+
+    assert!(matches!(read_container(&file[..file.len() - 1], true), Err(YamakagashiError::TruncatedStream { .. })));
+    assert!(matches!(read_container(&[file.clone(), vec![0]].concat(), true), Err(YamakagashiError::TrailingData { remaining: 1 })));
+    // 01 has no footer, so the crc is trailing data there
+    let legacy = [&b"YAMA01"[..], &file[SIGNATURE_SIZE..]].concat();
+    assert!(matches!(read_container(&legacy, true), Err(YamakagashiError::TrailingData { remaining: 4 })));
+    assert_eq!(read_container(&legacy[..legacy.len() - FOOTER_SIZE], true).unwrap().image_data, vec![7, 8, 9]);
+}
+#[test]
+fn version_test() {
+    let header = [0, 0, 0, 2, 0, 0, 0, 1, 1, 0, 0, 0, 0];
+    assert!(read_container(&[&b"YAMA01"[..], &header].concat(), true).is_ok());
+    assert!(matches!(read_container(&[&b"YAMA04"[..], &header].concat(), true), Err(YamakagashiError::UnsupportedHeader(_))));
+    assert!(matches!(read_container(&[&b"YAMB01"[..], &header].concat(), true), Err(YamakagashiError::UnsupportedHeader(_))));
+    assert!(matches!(read_container(b"YAMA", true), Err(YamakagashiError::TruncatedStream { .. })));
+    assert!(matches!(read_container(&[&b"YAMA01"[..], &header[..12]].concat(), true), Err(YamakagashiError::TruncatedStream { .. })));
+    // 03 is read as chunks, which want YHDR first
+    let mut chunked = b"YAMA03".to_vec();
+    write_chunk(&mut chunked, YDAT, &[0]).unwrap();
+    assert!(matches!(read_container(&chunked, true), Err(YamakagashiError::UnsupportedHeader(why)) if why.contains("first")));
+}
+#[test]
+fn chunks_test() {
+    let coding = CodingOptions { lossless_alpha: true, inter_row: true, entropy: Entropy::Zstd(3), varint_sizes: true, ..Default::default() };
+    let tile_index = TileIndex { tile_size: (2, 1), tiles: vec![(ScanDirection::Rows, 2), (ScanDirection::Columns, 1)] };
+    let yamakagashi = YamakagashiFile { image_size: (4, 1), number_of_colors: 2, bit_depth: 16, image_data: vec![1, 2, 3], text: vec![("Title".to_string(), "chunks".to_string())],
+        top_down: true, pels_per_meter: (2835, 3780), coding, tile_index: Some(tile_index) };
+    let mut file = Vec::new();
+    write_container(&mut file, &yamakagashi).unwrap();
+
+    let opened = read_container(&file, true).unwrap();
+    assert_eq!((opened.image_size, opened.number_of_colors, opened.bit_depth, &opened.image_data), ((4, 1), 2, 16, &vec![1, 2, 3]));
+    assert_eq!((&opened.text, opened.top_down, opened.pels_per_meter), (&yamakagashi.text, true, (2835, 3780)));
+    // the decoder takes the backend at its default level
+    assert_eq!(opened.coding, CodingOptions { entropy: Entropy::from_name("zstd").unwrap(), ..coding });
+    assert_eq!(opened.tile_index.map(|index| (index.tile_size, index.tiles)), Some(((2, 1), vec![(ScanDirection::Rows, 2), (ScanDirection::Columns, 1)])));
+
+    // chunks put in ahead of YEND
+    let with = |chunks: &[([u8; 4], &[u8])]| {
+        let mut bytes = file[..file.len() - CHUNK_OVERHEAD].to_vec();
+        chunks.iter().for_each(|&(chunk_type, data)| write_chunk(&mut bytes, chunk_type, data).unwrap());
+        write_chunk(&mut bytes, YEND, &[]).unwrap();
+        bytes
+    };
+    assert_eq!(read_container(&with(&[(*b"zzZZ", &[1, 2])]), true).unwrap().image_data, vec![1, 2, 3]);
+    assert!(matches!(read_container(&with(&[(*b"ZZZZ", &[])]), true), Err(YamakagashiError::UnsupportedHeader(_))));
+    assert!(matches!(read_container(&with(&[(YHDR, &file[14..28])]), true), Err(YamakagashiError::UnsupportedHeader(_))));
+    // YHDR has every field
+    let mut short = file[..SIGNATURE_SIZE].to_vec();
+    write_chunk(&mut short, YHDR, &file[14..27]).unwrap();
+    short.extend_from_slice(&file[SIGNATURE_SIZE + CHUNK_OVERHEAD + 14..]);
+    assert!(matches!(read_container(&short, true), Err(YamakagashiError::UnsupportedHeader(why)) if why.contains("13 bytes")));
+    assert!(matches!(read_container(&with(&[(YALP, &[1])]), true), Err(YamakagashiError::UnsupportedHeader(_))));
+    assert!(matches!(read_container(&with(&[(YDAT, &[4])]), true), Err(YamakagashiError::TrailingData { remaining: 1 })));
+
+    // a flipped byte of a chunk is caught unless verify is off
+    let mut damaged = file.clone();
+    let data_at = file.len() - CHUNK_OVERHEAD - 4 - 3;
+    damaged[data_at] ^= 1;
+    assert!(matches!(read_container(&damaged, true), Err(YamakagashiError::Corrupted { .. })));
+    assert_eq!(read_container(&damaged, false).unwrap().image_data, vec![0, 2, 3]);
+
+    assert!(matches!(read_container(&[file.clone(), vec![0]].concat(), true), Err(YamakagashiError::TrailingData { remaining: 1 })));
+    assert!(matches!(read_container(&file[..file.len() - CHUNK_OVERHEAD], true), Err(YamakagashiError::TruncatedStream { .. })));
+
+    // a tile the index can't record is refused instead of wrapping
+    let huge = YamakagashiFile { tile_index: Some(TileIndex { tile_size: (2, 1), tiles: vec![(ScanDirection::Rows, 1 << 32)] }), ..yamakagashi };
+    assert!(matches!(write_container(&mut Vec::new(), &huge), Err(YamakagashiError::UnsupportedHeader(_))));
+}
@@ -0,0 +1,124 @@
+use std::borrow::Cow;
+use std::path::Path;
+use yamakagashi_core::Sample;
+
+/// Image is what readers hand to the encoder and what the decoder hands to writers.
+/// pixels are gray or interleaved BGR, alpha optionally last, 8 or 16-bit or float, and rows are bottom-up, the layout the codec has always seen,
+/// the rest is kept only to write the image back the same way.
+pub struct Image {
+    pub size: (u32, u32),
+    // 1 is gray, 2 is gray and alpha, 3 is BGR, 4 is BGRA
+    pub number_of_colors: u8,
+    pub pixels: Pixels,
+    // rows of the source file were stored top-down
+    pub top_down: bool,
+    // physical resolution (x, y), 0 is unknown
+    pub pels_per_meter: (i32, i32),
+}
+
+pub enum Pixels {
+    Eight(Vec<u8>),
+    Sixteen(Vec<u16>),
+    // 0.0..=1.0 is black to white, HDR goes above it
+    Float(Vec<f32>),
+}
+
+impl Pixels {
+
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            Pixels::Eight(_) => 8,
+            Pixels::Sixteen(_) => 16,
+            Pixels::Float(_) => 32,
+        }
+    }
+
+    // samples cut to 8-bit, for writers of 8-bit only formats
+    pub fn to_eight(&self) -> Cow<'_, [u8]> {
+        match self {
+            Pixels::Eight(pixels) => Cow::Borrowed(pixels),
+            Pixels::Sixteen(pixels) => Cow::Owned(pixels.iter().map(|&sample| ((sample as u32 * 255 + 32767) / 65535) as u8).collect()),
+            Pixels::Float(pixels) => Cow::Owned(pixels.iter().map(|&sample| (sample.clamp(0.0, 1.0) * 255.0).round() as u8).collect()),
+        }
+    }
+
+    // samples widened or clamped to 16-bit, for writers which can't hold float
+    pub fn to_sixteen(&self) -> Cow<'_, [u16]> {
+        match self {
+            Pixels::Eight(pixels) => Cow::Owned(pixels.iter().map(|&sample| sample as u16 * 257).collect()),
+            Pixels::Sixteen(pixels) => Cow::Borrowed(pixels),
+            Pixels::Float(pixels) => Cow::Owned(pixels.iter().map(|&sample| (sample.clamp(0.0, 1.0) * 65535.0).round() as u16).collect()),
+        }
+    }
+}
+
+// B, G, R of one pixel, gray is copied to all of them
+pub fn to_bgr<T: Sample>(pixel: &[T]) -> [T; 3] {
+    match pixel {
+        &[gray] | &[gray, _] => [gray; 3],
+        _ => [pixel[0], pixel[1], pixel[2]],
+    }
+}
+
+// BT.601 luma of one pixel
+pub fn to_gray<T: Sample>(pixel: &[T]) -> T {
+    match pixel {
+        &[gray] | &[gray, _] => gray,
+        _ => {
+            let [b, g, r] = [pixel[0], pixel[1], pixel[2]].map(Into::<f32>::into);
+            T::from_f32((b * 114.0 + g * 587.0 + r * 299.0) / 1000.0)
+        },
+    }
+}
+
+// alpha of one pixel, opaque if it has none
+pub fn alpha_of<T: Sample>(pixel: &[T]) -> T {
+    match pixel {
+        &[_, alpha] | &[_, _, _, alpha] => alpha,
+        _ => T::MAX,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+    Pgm,
+    Ppm,
+    Pam,
+    Pfm,
+}
+
+impl ImageFormat {
+
+    // names accepted by --format, same as the file extensions
+    pub const NAMES: [&'static str; 6] = ["bmp", "png", "pgm", "ppm", "pam", "pfm"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bmp" | "dib" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            "pgm" => Some(ImageFormat::Pgm),
+            "ppm" | "pnm" => Some(ImageFormat::Ppm),
+            "pam" => Some(ImageFormat::Pam),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    // guess by file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension().and_then(|extension| extension.to_str()).and_then(ImageFormat::from_name)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+            ImageFormat::Pgm => "pgm",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Pam => "pam",
+            ImageFormat::Pfm => "pfm",
+        }
+    }
+}
@@ -1,185 +1,147 @@
-use std::fs::File; 
-use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
-use yamakagashi_core::{bitmap_to_yamakagashi, yamakagashi_to_bitmap};
+use yamakagashi_core::{bitmap_to_yamakagashi, bitmap_to_yamakagashi_any_scan, bitmap_to_yamakagashi_tiles, decode_region, Result, Sample, Tile, TileRect};
+use bitmap::{bitmap_opener, bitmap_writer};
+use container::{yamakagashi_opener, yamakagashi_writer, TileIndex, YamakagashiFile};
+use image::{Image, Pixels};
+use netpbm::{netpbm_opener, netpbm_writer};
+use png_file::{png_opener, png_writer};
+pub use image::ImageFormat;
+pub use yamakagashi_core::{ChromaSubsampling, CodingOptions, ColorTransform, Entropy, ScanDirection, YamakagashiError};
+
+mod bitmap;
+mod container;
+mod image;
+mod netpbm;
+mod png_file;
 
 // file io and format
 
-// encording
-pub fn do_encode(input_path:&PathBuf, output_path:&PathBuf, quality:i32) -> io::Result<()> {
-
-    let image_size: (u32, u32);
-    let bitmap_vec: Vec<u8>;
+// what do_encode found out on the way, for the caller to tell
+pub struct EncodeReport {
+    pub image_size: (u32, u32),
+    // scan direction of the image data, tiles record their own
+    pub scan: ScanDirection,
+    // scan direction of each tile, None when the image is one stream
+    pub tile_scans: Option<Vec<ScanDirection>>,
+}
 
-    (image_size, bitmap_vec) = bitmap_opener(input_path)?;
-    println!("width is : {}, height is : {}", image_size.0, image_size.1);
+// encording
+// format: input image format, None guesses it by extension of input_path, BMP if the extension is unknown
+// options: coding tools, lossless alpha is ignored if the image has no alpha,
+// color transform and subsampling are ignored if the image is gray, subsampling needs a color transform
+// auto_scan: encode along rows and along columns and keep the smaller, options.scan is ignored then
+// tile_size: code tiles of (width, height) each on its own, None codes the whole image as one
+pub fn do_encode(input_path:&PathBuf, output_path:&PathBuf, quality:i32, format:Option<ImageFormat>, options:CodingOptions, auto_scan:bool, tile_size:Option<(u32, u32)>) -> Result<EncodeReport> {
+
+    let format = format.or_else(|| ImageFormat::from_path(input_path)).unwrap_or(ImageFormat::Bmp);
+    let Image { size: image_size, number_of_colors, pixels, top_down, pels_per_meter } = match format {
+        ImageFormat::Bmp => bitmap_opener(input_path)?,
+        ImageFormat::Png => png_opener(input_path)?,
+        // magic number tells which Netpbm it is
+        ImageFormat::Pgm | ImageFormat::Ppm | ImageFormat::Pam | ImageFormat::Pfm => netpbm_opener(input_path)?,
+    };
+    let mut coding = options;
+    coding.lossless_alpha &= number_of_colors == 2 || number_of_colors == 4;
+    if number_of_colors < 3 {
+        coding.color_transform = ColorTransform::None;
+        coding.subsampling = ChromaSubsampling::Yuv444;
+    }
+    let bit_depth = pixels.bit_depth();
     // convert bitmap to yamakagashi
-    let yamakagashi_image_data = bitmap_to_yamakagashi(bitmap_vec, image_size, quality);
-    
-    // edit header
-    let signature = b"YAMA";
-    let version = b"01";
-    let width = image_size.0;
-    let height = image_size.1;
-    let number_of_colors = 3u8;
-
-    // edit chunks
-    let chunk_size = yamakagashi_image_data.len() as u32;
-
-    // edit footer
-    // let crc = ;
-    
-    // file output
-    let mut output_file = BufWriter::new(File::create(output_path)?);
+    let (coding, yamakagashi_image_data, tile_index) = match pixels {
+        Pixels::Eight(bitmap_vec) => encode_samples(bitmap_vec, number_of_colors, image_size, quality, coding, auto_scan, tile_size)?,
+        Pixels::Sixteen(bitmap_vec) => encode_samples(bitmap_vec, number_of_colors, image_size, quality, coding, auto_scan, tile_size)?,
+        Pixels::Float(bitmap_vec) => encode_samples(bitmap_vec, number_of_colors, image_size, quality, coding, auto_scan, tile_size)?,
+    };
+    let report = EncodeReport { image_size, scan: coding.scan, tile_scans: tile_index.as_ref().map(|index| index.tiles.iter().map(|&(scan, _)| scan).collect()) };
+
+    // edit header and chunks
+    let yamakagashi = YamakagashiFile {
+        image_size,
+        number_of_colors,
+        bit_depth,
+        image_data: yamakagashi_image_data,
+        text: vec![("Software".to_string(), format!("yamakagashi {}", env!("CARGO_PKG_VERSION")))],
+        top_down,
+        pels_per_meter,
+        coding,
+        tile_index,
+    };
 
-    // file write
-    output_file.write(signature)?;
-    output_file.write(version)?;
-    output_file.write_u32::<BigEndian>(width)?;
-    output_file.write_u32::<BigEndian>(height)?;
-    output_file.write_u8(number_of_colors)?;
-    output_file.write_u32::<BigEndian>(chunk_size)?;
-    output_file.write_all(&yamakagashi_image_data)?;
-    // output_file.write_u32::<BigEndian>(crc)?;
+    // file output
+    yamakagashi_writer(output_path, &yamakagashi)?;
 
-    output_file.flush()?;
+    Ok(report)
+}
 
-    Ok(())
+// image data of one sample type, tiles are put one after another and indexed
+fn encode_samples<S: Sample>(bitmap_vec:Vec<S>, number_of_colors:u8, image_size:(u32, u32), quality:i32, coding:CodingOptions, auto_scan:bool, tile_size:Option<(u32, u32)>) -> Result<(CodingOptions, Vec<u8>, Option<TileIndex>)> {
+
+    match (tile_size, auto_scan) {
+        (None, true) => bitmap_to_yamakagashi_any_scan(bitmap_vec, number_of_colors, image_size, quality, &coding).map(|(coding, data)| (coding, data, None)),
+        (None, false) => Ok((coding, bitmap_to_yamakagashi(bitmap_vec, number_of_colors, image_size, quality, &coding)?, None)),
+        (Some(tile_size), _) => {
+            let tiles = bitmap_to_yamakagashi_tiles(&bitmap_vec, number_of_colors, image_size, quality, &coding, tile_size, auto_scan)?;
+            let tile_index = TileIndex { tile_size, tiles: tiles.iter().map(|tile| (tile.scan, tile.data.len())).collect() };
+            // each tile records its own scan direction
+            Ok((CodingOptions { scan: ScanDirection::Rows, ..coding }, tiles.into_iter().flat_map(|tile| tile.data).collect(), Some(tile_index)))
+        },
+    }
 }
 
 // decording
-pub fn do_decode(input_path:&PathBuf, output_path:&PathBuf) -> io::Result<()> {
-
-    let image_size: (u32, u32);
-    let number_of_colors: u8;
-    let yamakagashi_image_data: Vec<u8>;
-
-    (image_size, number_of_colors, yamakagashi_image_data) = yamakagashi_opener(input_path)?;
+// verify: check crc, pass false to decode a damaged file anyway
+// format: output image format, None guesses it by extension of output_path, BMP if the extension is unknown
+// region: (x, y, width, height) from the top left of the image to write alone, None writes the whole image
+// returns the (keyword, text) of the file for the caller to tell
+pub fn do_decode(input_path:&PathBuf, output_path:&PathBuf, verify:bool, format:Option<ImageFormat>, region:Option<TileRect>) -> Result<Vec<(String, String)>> {
+
+    let YamakagashiFile { image_size, number_of_colors, bit_depth, image_data: yamakagashi_image_data, text, top_down, pels_per_meter, coding, tile_index } = yamakagashi_opener(input_path, verify)?;
+    // writers only know gray and BGR, with or without alpha
+    if !(1..=4).contains(&number_of_colors) {return Err(YamakagashiError::UnsupportedHeader(format!("{number_of_colors} colors image can't be written")));}
+
+    // image data is bottom-up, so its rows count from the bottom
+    let region = match region {
+        None => (0, 0, image_size.0, image_size.1),
+        Some((x, y, width, height)) => {
+            let bottom = y.checked_add(height).and_then(|top| image_size.1.checked_sub(top))
+                .ok_or(YamakagashiError::RegionOutOfImage { region: (x, y, width, height), size: image_size })?;
+            (x, bottom, width, height)
+        },
+    };
 
     // convert yamakagashi to bitmap
-    let bitmap_vec = yamakagashi_to_bitmap(yamakagashi_image_data, number_of_colors, image_size);
-    
-    let row_size = (3 * image_size.0 + 3) & !3; // 24ビットカラー、各行は4バイトの倍数にパディング
-    let image_size_with_padding = row_size * image_size.1;
-    
-    
-    
-    
-    
-    // edit header
-    let signature = b"BM";
-    let file_size = (14 + 40 + image_size_with_padding) as u32;
-    let booking_space = &[0, 0, 0, 0];
-    let image_data_offset: u32 = 14 + 40;
-
-    let header_size = 40u32;
-    let width = image_size.0;
-    let height = image_size.1;
-    let planes = 1u16;
-    let bit_count = 24u16;
-
-    let compression = 0u32;
-    let size_image = image_size_with_padding as u32;
-    let x_pels_per_meter = 0i32;
-    let y_pels_per_meter = 0i32;
-    let clr_used = 0u32;
-    let clr_important = 0u32;
-
-    // file output
-    let mut output_file = BufWriter::new(File::create(output_path)?);
-
-    output_file.write(signature)?;
-    output_file.write_u32::<LittleEndian>(file_size)?;
-    output_file.write(booking_space)?;
-    output_file.write_u32::<LittleEndian>(image_data_offset)?;
-    output_file.write_u32::<LittleEndian>(header_size)?;
-    output_file.write_u32::<LittleEndian>(width)?;
-    output_file.write_u32::<LittleEndian>(height)?;
-    output_file.write_u16::<LittleEndian>(planes)?;
-    output_file.write_u16::<LittleEndian>(bit_count)?;
-
-    output_file.write_u32::<LittleEndian>(compression)?;
-    output_file.write_u32::<LittleEndian>(size_image)?;
-    output_file.write_i32::<LittleEndian>(x_pels_per_meter)?;
-    output_file.write_i32::<LittleEndian>(y_pels_per_meter)?;
-    output_file.write_u32::<LittleEndian>(clr_used)?;
-    output_file.write_u32::<LittleEndian>(clr_important)?;
-
-    let row_padding = &vec![0; ((4-(width*3%4))%4) as usize];
-    for row in bitmap_vec.chunks((number_of_colors as u32*width) as usize) {
-        output_file.write_all(row)?;
-        output_file.write_all(row_padding)?;
+    let pixels = match bit_depth {
+        8 => Pixels::Eight(decode_samples(yamakagashi_image_data, number_of_colors, image_size, &coding, tile_index, region)?),
+        16 => Pixels::Sixteen(decode_samples(yamakagashi_image_data, number_of_colors, image_size, &coding, tile_index, region)?),
+        32 => Pixels::Float(decode_samples(yamakagashi_image_data, number_of_colors, image_size, &coding, tile_index, region)?),
+        _ => return Err(YamakagashiError::UnsupportedHeader(format!("{bit_depth}-bit samples can't be decoded"))),
+    };
+    let image = Image { size: (region.2, region.3), number_of_colors, pixels, top_down, pels_per_meter };
+
+    match format.or_else(|| ImageFormat::from_path(output_path)).unwrap_or(ImageFormat::Bmp) {
+        ImageFormat::Bmp => bitmap_writer(output_path, &image)?,
+        ImageFormat::Png => png_writer(output_path, &image)?,
+        format => netpbm_writer(output_path, &image, format)?,
     }
 
-    output_file.flush()?;
-
-    Ok(())
+    Ok(text)
 }
 
-fn bitmap_opener(input_path:&PathBuf) -> io::Result<((u32, u32), Vec<u8>)> {
-
-    // file input
-    let mut input_file = File::open(&input_path)?;
-
-    // bitmap file header
-    // signature check
-    let mut file_header = [0; 14];
-    input_file.read_exact(&mut file_header)?;
-    if &file_header[..2] != b"BM" {return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a BMP file"));}
-
-    let header_size = input_file.read_u32::<LittleEndian>()?;
-    let width = input_file.read_i32::<LittleEndian>()?;
-    if width < 0 {return Err(io::Error::new(io::ErrorKind::InvalidData, "Only positive width size BMP files are supported"));}
-    let height = input_file.read_i32::<LittleEndian>()?;
-    // if height > 0 {return Err(io::Error::new(io::ErrorKind::InvalidData, "Only negative height size BMP files are supported"));}
-    let _planes = input_file.read_u16::<LittleEndian>()?;
-    let bit_count = input_file.read_u16::<LittleEndian>()?;
-
-    if bit_count != 24 {return Err(io::Error::new(io::ErrorKind::InvalidData, "Only 24-bit BMP files are supported"));}
-
-    let _compression = input_file.read_u32::<LittleEndian>()?;
-    let _size_image = input_file.read_u32::<LittleEndian>()?;
-    let _x_pels_per_meter = input_file.read_i32::<LittleEndian>()?;
-    let _y_pels_per_meter = input_file.read_i32::<LittleEndian>()?;
-    let _clr_used = input_file.read_u32::<LittleEndian>()?;
-    let _clr_important = input_file.read_u32::<LittleEndian>()?;
-
-    let row_size = ((24 * width as u32 + 31) / 32) * 4;
-    let pixel_row_size = (width * 3) as usize;
-    let mut pixel_data = Vec::with_capacity(pixel_row_size * height as usize);
-
-    input_file.seek(SeekFrom::Start(file_header.len() as u64 + header_size as u64))?;
-    for _ in 0..height {
-        let mut row = vec![0u8; row_size as usize];
-        input_file.read_exact(&mut row)?;
-        pixel_data.extend_from_slice(&row[0..pixel_row_size]);
-    }
-
-    Ok(((width.abs() as u32, height.abs() as u32), pixel_data))
-
+// samples of region of image data, the container has checked the tiles cover it exactly
+// image data of one stream is one tile as big as the image
+fn decode_samples<S: Sample>(yamakagashi_image_data:Vec<u8>, number_of_colors:u8, image_size:(u32, u32), coding:&CodingOptions, tile_index:Option<TileIndex>, region:TileRect) -> Result<Vec<S>> {
+
+    let Some(TileIndex { tile_size, tiles }) = tile_index else {
+        let tile = Tile { scan: coding.scan, data: yamakagashi_image_data };
+        return decode_region(&[tile], number_of_colors, image_size, coding, image_size, region);
+    };
+    let mut rest = &yamakagashi_image_data[..];
+    let tiles: Vec<Tile> = tiles.into_iter().map(|(scan, bytes)| {
+        let (data, next) = rest.split_at(bytes);
+        rest = next;
+        Tile { scan, data: data.to_vec() }
+    }).collect();
+
+    decode_region(&tiles, number_of_colors, image_size, coding, tile_size, region)
 }
-
-fn yamakagashi_opener(input_path:&PathBuf) -> io::Result<((u32, u32), u8, Vec<u8>)> {
-
-    // file input
-    let mut input_file = File::open(&input_path)?;
-
-    // signature check
-    let mut signature = [0; 4];
-    input_file.read_exact(&mut signature)?;
-    if &signature != b"YAMA" {return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a YAMAKAGASHI file"));}
-
-    let mut virsion = [0; 2];
-    input_file.read_exact(&mut virsion)?;
-    
-    let width = input_file.read_u32::<BigEndian>()?;
-    let height = input_file.read_u32::<BigEndian>()?;
-    let number_of_colors = input_file.read_u8()?;
-    let chunk_size = input_file.read_u32::<BigEndian>()?;
-    let mut yamakagashi_image_data = Vec::with_capacity(chunk_size as usize); 
-    input_file.read_to_end(&mut yamakagashi_image_data)?;
-
-
-    Ok(((width, height),number_of_colors, yamakagashi_image_data))
-}
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
-use clap::{self, Arg, Command};
-use yamakagashi::{do_encode, do_decode};
+use clap::{self, Arg, ArgAction, Command};
+use yamakagashi::{do_encode, do_decode, ChromaSubsampling, CodingOptions, ColorTransform, Entropy, ImageFormat, ScanDirection};
 
 /*{
 #[derive(Parser, Debug)]
@@ -25,6 +25,26 @@ enum Subcommands {
     decode,
 } }*/
 
+// 256 is 256x256, 4096x16 is a row band of an image up to 4096 wide
+fn parse_tile_size(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value.split_once('x').unwrap_or((value, value));
+    match (width.parse::<u32>(), height.parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("{value} is not a tile size like 256 or 256x64")),
+    }
+}
+
+// 100,40,640x480 is 640x480 pixels at 100 from the left and 40 from the top
+fn parse_region(value: &str) -> Result<(u32, u32, u32, u32), String> {
+    let parts: Option<(&str, &str, &str, &str)> = value.split_once(',')
+        .and_then(|(x, rest)| rest.split_once(',').map(|(y, size)| (x, y, size)))
+        .and_then(|(x, y, size)| size.split_once('x').map(|(width, height)| (x, y, width, height)));
+    match parts.map(|(x, y, width, height)| (x.parse::<u32>(), y.parse::<u32>(), width.parse::<u32>(), height.parse::<u32>())) {
+        Some((Ok(x), Ok(y), Ok(width), Ok(height))) => Ok((x, y, width, height)),
+        _ => Err(format!("{value} is not a region like 100,40,640x480")),
+    }
+}
+
 fn main() {
     
     /* env analyze
@@ -40,11 +60,26 @@ fn main() {
                 .arg(Arg::new("input_path").required(true).index(1).value_parser(clap::value_parser!(PathBuf)))
                 .arg(Arg::new("output_path").required(false).index(2).value_parser(clap::value_parser!(PathBuf)))
                 .arg(Arg::new("quality").required(false).index(3).value_parser(clap::value_parser!(i32).range(0..=100)))
+                .arg(Arg::new("format").long("format").value_parser(ImageFormat::NAMES).help("input image format, guessed by extension if omitted"))
+                .arg(Arg::new("lossless_alpha").long("lossless-alpha").action(ArgAction::SetTrue).help("keep alpha exactly while colors are lossy"))
+                .arg(Arg::new("color_transform").long("color-transform").value_parser(ColorTransform::NAMES).help("decorrelate colors before coding, none if omitted"))
+                .arg(Arg::new("subsampling").long("subsampling").value_parser(ChromaSubsampling::NAMES).help("chroma resolution with a color transform, 444 if omitted"))
+                .arg(Arg::new("shared_units").long("shared-units").action(ArgAction::SetTrue).help("cut all planes into units at the same points"))
+                .arg(Arg::new("inter_row").long("inter-row").action(ArgAction::SetTrue).help("predict unit boundaries and coeffs from the row above"))
+                .arg(Arg::new("patches").long("patches").action(ArgAction::SetTrue).help("code smooth areas as 2-D patches"))
+                .arg(Arg::new("varint_sizes").long("varint-sizes").action(ArgAction::SetTrue).help("write unit sizes and coeff counts as varints, units may run over 65535 samples"))
+                .arg(Arg::new("tile_size").long("tile-size").value_parser(parse_tile_size).help("code tiles of WIDTHxHEIGHT or SIZE square each on its own, one tile if omitted"))
+                .arg(Arg::new("scan").long("scan").value_parser(["rows", "columns", "auto"]).help("direction units run in, auto tries both and keeps the smaller, auto if omitted"))
+                .arg(Arg::new("entropy").long("entropy").value_parser(Entropy::NAMES).help("backend packing the coded units, xz if omitted"))
+                .arg(Arg::new("entropy_level").long("entropy-level").value_parser(clap::value_parser!(u32)).help("preset of xz, level of zstd or quality of brotli, the default of the backend if omitted"))
             )
         .subcommand(
             Command::new("decode")
                 .arg(Arg::new("input_path").required(true).index(1).value_parser(clap::value_parser!(PathBuf)))
                 .arg(Arg::new("output_path").required(false).index(2).value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("no_verify").long("no-verify").action(ArgAction::SetTrue).help("decode even if the crc doesn't match"))
+                .arg(Arg::new("format").long("format").value_parser(ImageFormat::NAMES).help("output image format, guessed by extension if omitted"))
+                .arg(Arg::new("region").long("region").value_parser(parse_region).help("decode only X,Y,WIDTHxHEIGHT from the top left, the whole image if omitted"))
             )
         .get_matches();
 
@@ -68,17 +103,50 @@ fn main() {
                 Some(quality) => *quality,
                 _ => DEFAULT_QUALITY
             };
-            do_encode(input_path, output_path, quality)
+            let format = matches.get_one::<String>("format").and_then(|name| ImageFormat::from_name(name));
+            let options = CodingOptions {
+                lossless_alpha: matches.get_flag("lossless_alpha"),
+                color_transform: matches.get_one::<String>("color_transform").and_then(|name| ColorTransform::from_name(name)).unwrap_or_default(),
+                subsampling: matches.get_one::<String>("subsampling").and_then(|name| ChromaSubsampling::from_name(name)).unwrap_or_default(),
+                shared_units: matches.get_flag("shared_units"),
+                inter_row: matches.get_flag("inter_row"),
+                patches: matches.get_flag("patches"),
+                scan: matches.get_one::<String>("scan").and_then(|name| ScanDirection::from_name(name)).unwrap_or_default(),
+                entropy: matches.get_one::<String>("entropy").and_then(|name| Entropy::from_name(name)).unwrap_or_default(),
+                trailing_zeros: false,
+                varint_sizes: matches.get_flag("varint_sizes"),
+            };
+            let options = match matches.get_one::<u32>("entropy_level") {
+                Some(&level) => CodingOptions { entropy: options.entropy.with_level(level), ..options },
+                _ => options,
+            };
+            let auto_scan = matches.get_one::<String>("scan").is_none_or(|name| name == "auto");
+            let tile_size = matches.get_one::<(u32, u32)>("tile_size").copied();
+            do_encode(input_path, output_path, quality, format, options, auto_scan, tile_size).map(|report| {
+                println!("width is : {}, height is : {}", report.image_size.0, report.image_size.1);
+                match (&report.tile_scans, auto_scan) {
+                    (None, true) => println!("scan direction is : {}", report.scan.name()),
+                    (Some(tile_scans), true) => println!("tiles along columns : {} of {}", tile_scans.iter().filter(|&&scan| scan == ScanDirection::Columns).count(), tile_scans.len()),
+                    _ => (),
+                }
+            })
         }
 
 
         Some(("decode", matches)) => {
             let input_path = matches.get_one::<PathBuf>("input_path").unwrap();
+            let format = matches.get_one::<String>("format").and_then(|name| ImageFormat::from_name(name));
             let output_path = match matches.get_one::<PathBuf>("output_path") {
                 Some(output_path) => output_path,
-                _ => &PathBuf::from(input_path.file_name().unwrap()).with_extension("bmp"),
+                _ => &PathBuf::from(input_path.file_name().unwrap()).with_extension(format.unwrap_or(ImageFormat::Bmp).extension()),
             };
-            do_decode(input_path, output_path)},
+            let verify = !matches.get_flag("no_verify");
+            let region = matches.get_one::<(u32, u32, u32, u32)>("region").copied();
+            do_decode(input_path, output_path, verify, format, region).map(|text| {
+                for (keyword, text) in text {
+                    println!("{keyword} : {text}");
+                }
+            })},
 
         _ => unreachable!("Exhausted list of subcommands and subcommand_required prevents `None`"),
     };
@@ -0,0 +1,310 @@
+//! Netpbm (PGM, PPM, PAM) and PFM reader and writer
+//!
+//! reader accepts P2, P3, P5, P6 with maxval up to 65535, P7 with GRAYSCALE, RGB and their _ALPHA tuples,
+//! and Pf, PF float maps. samples are scaled to 8-bit, or to 16-bit when maxval is over 255,
+//! gray stays one channel, alpha is kept as the last channel, and float samples stay float, NaN and infinity become 0.
+//! writer writes P5, P6, P7 (GRAYSCALE, RGB and their _ALPHA) of 8 or 16-bit and little-endian Pf or PF,
+//! float is clamped to 16-bit for P5, P6 and P7.
+//! only P7 can hold alpha, the others drop it.
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use byteorder::{LittleEndian, WriteBytesExt};
+use yamakagashi_core::{Result, Sample, YamakagashiError};
+use crate::image::{alpha_of, to_bgr, to_gray, Image, ImageFormat, Pixels};
+
+pub fn netpbm_opener(input_path:&PathBuf) -> Result<Image> {
+
+    // file input
+    let mut file_bytes = Vec::new();
+    File::open(input_path)?.read_to_end(&mut file_bytes)?;
+    let mut header = HeaderReader { bytes: &file_bytes, index: 2 };
+
+    // signature check
+    let magic = file_bytes.get(..2).unwrap_or_default();
+    let (width, height, channels, maxval) = match magic {
+        b"P2" | b"P5" => (header.number()?, header.number()?, 1, header.number()?),
+        b"P3" | b"P6" => (header.number()?, header.number()?, 3, header.number()?),
+        b"P7" => header.pam_header()?,
+        b"Pf" | b"PF" => return pfm_opener(&file_bytes, &mut header),
+        _ => return Err(YamakagashiError::UnsupportedHeader("Not a PGM, PPM, PAM or PFM file".to_string())),
+    };
+    if width == 0 || height == 0 {return Err(YamakagashiError::UnsupportedHeader("Netpbm width or height is 0".to_string()));}
+    if maxval == 0 || maxval > 65535 {return Err(YamakagashiError::UnsupportedHeader(format!("Netpbm maxval {maxval} is not supported")));}
+
+    let samples = sample_count(width, height, channels)?;
+    let mut values: Vec<u32> = Vec::new();
+    if magic == b"P2" || magic == b"P3" {
+        // plain format, samples are decimal numbers
+        for _ in 0..samples {
+            values.push(header.number()?);
+        }
+    } else {
+        // raw format, exactly one whitespace before samples (P7 already consumed ENDHDR line)
+        if magic != b"P7" { header.index += 1; }
+        let bytes_per_sample = if maxval > 255 { 2 } else { 1 };
+        let needed = header.index + samples * bytes_per_sample;
+        if file_bytes.len() < needed {
+            return Err(YamakagashiError::TruncatedStream { needed, available: file_bytes.len() });
+        }
+        values = file_bytes[header.index..needed].chunks(bytes_per_sample)
+            .map(|sample| sample.iter().fold(0u32, |acc, &byte| acc << 8 | byte as u32)).collect();
+    }
+
+    // maxval up to 255 is scaled to 8-bit, above it to 16-bit
+    let pixels = if maxval > 255 {
+        Pixels::Sixteen(to_internal(&values, width as usize, channels, |value| ((value.min(maxval) * 65535 + maxval / 2) / maxval) as u16))
+    } else {
+        Pixels::Eight(to_internal(&values, width as usize, channels, |value| ((value.min(maxval) * 255 + maxval / 2) / maxval) as u8))
+    };
+
+    Ok(Image { size: (width, height), number_of_colors: channels as u8, pixels, top_down: true, pels_per_meter: (0, 0) })
+}
+
+fn pfm_opener(file_bytes:&[u8], header:&mut HeaderReader) -> Result<Image> {
+
+    let channels = if &file_bytes[..2] == b"PF" { 3 } else { 1 };
+    let width = header.number()?;
+    let height = header.number()?;
+    let scale: f32 = header.token()?.parse().map_err(|_| YamakagashiError::UnsupportedHeader("PFM scale is not a number".to_string()))?;
+    if width == 0 || height == 0 {return Err(YamakagashiError::UnsupportedHeader("PFM width or height is 0".to_string()));}
+    // exactly one whitespace before samples
+    header.index += 1;
+
+    let samples = sample_count(width, height, channels)?;
+    let needed = header.index + samples * 4;
+    if file_bytes.len() < needed {
+        return Err(YamakagashiError::TruncatedStream { needed, available: file_bytes.len() });
+    }
+
+    // negative scale means little-endian
+    let values: Vec<f32> = file_bytes[header.index..needed].chunks(4).map(|sample| {
+        let bytes = [sample[0], sample[1], sample[2], sample[3]];
+        if scale < 0.0 { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }
+    }).collect();
+
+    // PFM rows are already bottom-up, NaN and infinity can't be coded
+    let finite = |value: f32| if value.is_finite() { value } else { 0.0 };
+    let mut pixels = Vec::with_capacity(samples);
+    for pixel in values.chunks(channels) {
+        match channels {
+            1 => pixels.push(finite(pixel[0])),
+            _ => pixels.extend([finite(pixel[2]), finite(pixel[1]), finite(pixel[0])]),
+        }
+    }
+
+    Ok(Image { size: (width, height), number_of_colors: channels as u8, pixels: Pixels::Float(pixels), top_down: false, pels_per_meter: (0, 0) })
+}
+
+// Netpbm is top-down RGB, internal buffer is bottom-up BGR
+fn to_internal<T>(values:&[u32], width:usize, channels:usize, scale:impl Fn(u32) -> T) -> Vec<T> {
+
+    let mut pixels = Vec::with_capacity(values.len());
+    for row in values.chunks(width * channels).rev() {
+        for pixel in row.chunks(channels) {
+            match channels {
+                1 | 2 => pixels.extend(pixel.iter().map(|&value| scale(value))),
+                3 => pixels.extend([scale(pixel[2]), scale(pixel[1]), scale(pixel[0])]),
+                _ => pixels.extend([scale(pixel[2]), scale(pixel[1]), scale(pixel[0]), scale(pixel[3])]),
+            }
+        }
+    }
+
+    pixels
+}
+
+// width * height * channels, refused if 4 bytes samples of it can't even be addressed
+fn sample_count(width:u32, height:u32, channels:usize) -> Result<usize> {
+    let samples = (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(channels));
+    match samples {
+        Some(samples) if samples.checked_mul(4).is_some() => Ok(samples),
+        _ => Err(YamakagashiError::UnsupportedHeader(format!("image size {width}x{height} is too big"))),
+    }
+}
+
+// whitespace separated tokens and # comments of Netpbm header
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl HeaderReader<'_> {
+
+    fn token(&mut self) -> Result<String> {
+
+        // skip whitespace and comments
+        while let Some(&byte) = self.bytes.get(self.index) {
+            if byte == b'#' {
+                while self.bytes.get(self.index).is_some_and(|&byte| byte != b'\n') { self.index += 1; }
+            } else if byte.is_ascii_whitespace() {
+                self.index += 1;
+            } else {
+                break;
+            }
+        }
+
+        let start = self.index;
+        while self.bytes.get(self.index).is_some_and(|byte| !byte.is_ascii_whitespace()) { self.index += 1; }
+        if start == self.index {
+            return Err(YamakagashiError::TruncatedStream { needed: self.index + 1, available: self.bytes.len() });
+        }
+
+        Ok(String::from_utf8_lossy(&self.bytes[start..self.index]).into_owned())
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        let token = self.token()?;
+        token.parse().map_err(|_| YamakagashiError::UnsupportedHeader(format!("Netpbm header has {token:?} instead of a number")))
+    }
+
+    // (width, height, depth, maxval) of P7, index is left just after ENDHDR line
+    fn pam_header(&mut self) -> Result<(u32, u32, usize, u32)> {
+
+        let (mut width, mut height, mut depth, mut maxval) = (0, 0, 0, 0);
+        loop {
+            match self.token()?.as_str() {
+                "WIDTH" => width = self.number()?,
+                "HEIGHT" => height = self.number()?,
+                "DEPTH" => depth = self.number()?,
+                "MAXVAL" => maxval = self.number()?,
+                "TUPLTYPE" => { self.token()?; },
+                "ENDHDR" => break,
+                token => return Err(YamakagashiError::UnsupportedHeader(format!("PAM header has unknown field {token:?}"))),
+            }
+        }
+        // ENDHDR ends with a newline
+        self.index += 1;
+
+        if !(1..=4).contains(&depth) {return Err(YamakagashiError::UnsupportedHeader(format!("PAM depth {depth} is not supported")));}
+
+        Ok((width, height, depth as usize, maxval))
+    }
+}
+
+// write PGM, PPM, PAM with maxval 255 or 65535 by the bit depth of the image, or PFM
+pub fn netpbm_writer(output_path:&PathBuf, image:&Image, format:ImageFormat) -> Result<()> {
+
+    // file output
+    let mut output_file = BufWriter::new(File::create(output_path)?);
+
+    match &image.pixels {
+        Pixels::Eight(pixels) => write_netpbm(&mut output_file, pixels, image, format)?,
+        Pixels::Sixteen(pixels) => write_netpbm(&mut output_file, pixels, image, format)?,
+        Pixels::Float(pixels) if format == ImageFormat::Pfm => write_netpbm(&mut output_file, pixels, image, format)?,
+        Pixels::Float(_) => write_netpbm(&mut output_file, &image.pixels.to_sixteen(), image, format)?,
+    }
+
+    output_file.flush()?;
+
+    Ok(())
+}
+
+fn write_netpbm<W: Write, T: Sample>(output_file:&mut W, pixels:&[T], image:&Image, format:ImageFormat) -> Result<()> {
+
+    let (width, height) = image.size;
+    let channels = image.number_of_colors as usize;
+    let maxval: f32 = T::MAX.into();
+    // PAM and PFM keep gray as it is, PGM is always gray and PPM always color
+    let gray = match format {
+        ImageFormat::Pgm => true,
+        ImageFormat::Ppm => false,
+        _ => channels <= 2,
+    };
+    let alpha = format == ImageFormat::Pam && (channels == 2 || channels == 4);
+    // samples per pixel written
+    let depth = if gray { 1 } else { 3 } + alpha as usize;
+
+    match format {
+        ImageFormat::Pgm => write!(output_file, "P5\n{width} {height}\n{maxval}\n")?,
+        ImageFormat::Ppm => write!(output_file, "P6\n{width} {height}\n{maxval}\n")?,
+        ImageFormat::Pam => {
+            let tuple_type = [if gray { "GRAYSCALE" } else { "RGB" }, if alpha { "_ALPHA" } else { "" }].concat();
+            write!(output_file, "P7\nWIDTH {width}\nHEIGHT {height}\nDEPTH {depth}\nMAXVAL {maxval}\nTUPLTYPE {tuple_type}\nENDHDR\n")?
+        },
+        ImageFormat::Pfm if gray => write!(output_file, "Pf\n{width} {height}\n-1.0\n")?,
+        ImageFormat::Pfm => write!(output_file, "PF\n{width} {height}\n-1.0\n")?,
+        _ => unreachable!("netpbm_writer is called only for Netpbm formats"),
+    }
+
+    // PFM is bottom-up like internal buffer, the others are top-down
+    let rows = pixels.chunks(channels * width as usize);
+    let rows: Box<dyn Iterator<Item = &[T]>> = if format == ImageFormat::Pfm { Box::new(rows) } else { Box::new(rows.rev()) };
+    let mut row_bytes = Vec::new();
+    for row in rows {
+        row_bytes.clear();
+        for pixel in row.chunks(channels) {
+            let [b, g, r] = to_bgr(pixel);
+            let gray_alpha = [to_gray(pixel), alpha_of(pixel)];
+            let rgba = [r, g, b, alpha_of(pixel)];
+            let samples: &[T] = if gray { &gray_alpha[..depth] } else { &rgba[..depth] };
+            for &sample in samples {
+                match format {
+                    ImageFormat::Pfm => row_bytes.write_f32::<LittleEndian>(Into::<f32>::into(sample) / maxval)?,
+                    // Netpbm samples are big-endian
+                    _ => sample.write_be(&mut row_bytes),
+                }
+            }
+        }
+        output_file.write_all(&row_bytes)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn netpbm_test() {
+    let path = std::env::temp_dir().join(format!("yamakagashi_netpbm_test_{}", std::process::id()));
+    let size = (5u32, 3u32);
+    let eight = |number_of_colors: u8| -> Vec<u8> { (0..size.0 * size.1 * number_of_colors as u32).map(|i| (i * 37 % 256) as u8).collect() };
+
+    // P5, P6 and P7 of every layout they hold keep the samples as they are
+    for (format, number_of_colors) in [(ImageFormat::Pgm, 1u8), (ImageFormat::Ppm, 3), (ImageFormat::Pam, 2), (ImageFormat::Pam, 4)] {
+        let image = Image { size, number_of_colors, pixels: Pixels::Eight(eight(number_of_colors)), top_down: true, pels_per_meter: (0, 0) };
+        netpbm_writer(&path, &image, format).unwrap();
+        let read = netpbm_opener(&path).unwrap();
+        assert_eq!((read.size, read.number_of_colors), (size, number_of_colors));
+        assert!(matches!(read.pixels, Pixels::Eight(read) if read == eight(number_of_colors)), "{format:?} of {number_of_colors} colors");
+    }
+    let sixteen: Vec<u16> = (0..size.0 * size.1 * 3).map(|i| (i * 4099 % 65536) as u16).collect();
+    netpbm_writer(&path, &Image { size, number_of_colors: 3, pixels: Pixels::Sixteen(sixteen.clone()), top_down: true, pels_per_meter: (0, 0) }, ImageFormat::Ppm).unwrap();
+    assert!(matches!(netpbm_opener(&path).unwrap().pixels, Pixels::Sixteen(read) if read == sixteen));
+    for number_of_colors in [1u8, 3] {
+        let float: Vec<f32> = (0..size.0 * size.1 * number_of_colors as u32).map(|i| i as f32 / 7.0).collect();
+        netpbm_writer(&path, &Image { size, number_of_colors, pixels: Pixels::Float(float.clone()), top_down: false, pels_per_meter: (0, 0) }, ImageFormat::Pfm).unwrap();
+        assert!(matches!(netpbm_opener(&path).unwrap().pixels, Pixels::Float(read) if read == float), "PFM of {number_of_colors} colors");
+    }
+
+    // plain P2 and P3 with comments, maxval scaled to 8-bit, rows turned bottom-up and RGB to BGR
+    std::fs::write(&path, "P2 # gray\n2 2\n# maxval\n15\n0 15\n5 10\n").unwrap();
+    assert!(matches!(netpbm_opener(&path).unwrap().pixels, Pixels::Eight(read) if read == [85, 170, 0, 255]));
+    std::fs::write(&path, "P3\n1 1\n255\n10 20 30\n").unwrap();
+    assert!(matches!(netpbm_opener(&path).unwrap().pixels, Pixels::Eight(read) if read == [30, 20, 10]));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn malformed_netpbm_test() {
+    let path = std::env::temp_dir().join(format!("yamakagashi_malformed_netpbm_test_{}", std::process::id()));
+
+    let headers: [&[u8]; 7] = [
+        b"P4\n1 1\n\x00",
+        b"P5\n0 1\n255\n",
+        b"P5\n1 1\n70000\n\x00\x00",
+        b"P5\n1 x\n255\n\x00",
+        b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nENDHDR\n\x00\x00\x00\x00\x00",
+        b"P7\nWIDTH 1\nHEIGHT 1\nCOLORS 3\nENDHDR\n",
+        b"PF\n1 1\nscale\n",
+    ];
+    for header in headers {
+        std::fs::write(&path, header).unwrap();
+        assert!(matches!(netpbm_opener(&path), Err(YamakagashiError::UnsupportedHeader(_))), "{:?}", String::from_utf8_lossy(header));
+    }
+    // fewer samples than the header asks for
+    for header in [&b"P6\n2 2\n255\n\x00\x00\x00"[..], b"P2\n2 1\n255\n7", b"Pf\n2 1\n-1.0\n\x00\x00\x00\x00"] {
+        std::fs::write(&path, header).unwrap();
+        assert!(matches!(netpbm_opener(&path), Err(YamakagashiError::TruncatedStream { .. })), "{:?}", String::from_utf8_lossy(header));
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
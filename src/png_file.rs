@@ -0,0 +1,195 @@
+//! PNG reader and writer
+//!
+//! reader accepts every color type and bit depth, palette and low bit depth are expanded,
+//! 16-bit samples stay 16-bit, gray stays one channel, and alpha is kept as the last channel.
+//! writer writes gray, gray and alpha, RGB or RGBA of the image's bit depth, float is clamped to 16-bit,
+//! or 8-bit palette when an opaque 8-bit color image has few enough colors.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use png::{BitDepth, ColorType, Decoder, DecodingError, Encoder, EncodingError, Limits, PixelDimensions, Transformations, Unit};
+use yamakagashi_core::{Result, YamakagashiError};
+use crate::image::{Image, Pixels};
+
+// default limit of png crate is 64MiB, too small for a photo
+const PNG_MEMORY_LIMIT: usize = 1 << 30;
+
+pub fn png_opener(input_path:&PathBuf) -> Result<Image> {
+
+    // file input
+    let mut decoder = Decoder::new_with_limits(BufReader::new(File::open(input_path)?), Limits { bytes: PNG_MEMORY_LIMIT });
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(decoding_error)?;
+
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let output_info = reader.next_frame(&mut buffer).map_err(decoding_error)?;
+    let (width, height) = (output_info.width, output_info.height);
+    let buffer = &buffer[..output_info.buffer_size()];
+
+    let pels_per_meter = match reader.info().pixel_dims {
+        Some(PixelDimensions { xppu, yppu, unit: Unit::Meter }) => (xppu as i32, yppu as i32),
+        _ => (0, 0),
+    };
+
+    let number_of_colors = output_info.color_type.samples();
+    let pixels = match output_info.bit_depth {
+        BitDepth::Sixteen => {
+            let samples: Vec<u16> = buffer.chunks(2).map(|sample| u16::from_be_bytes([sample[0], sample[1]])).collect();
+            Pixels::Sixteen(flip_and_swap(&samples, width as usize, number_of_colors))
+        },
+        // palette and low bit depth are already expanded to 8-bit
+        _ => Pixels::Eight(flip_and_swap(buffer, width as usize, number_of_colors)),
+    };
+
+    Ok(Image { size: (width, height), number_of_colors: number_of_colors as u8, pixels, top_down: true, pels_per_meter })
+}
+
+pub fn png_writer(output_path:&PathBuf, image:&Image) -> Result<()> {
+
+    let (width, height) = image.size;
+    let channels = image.number_of_colors as usize;
+
+    // file output
+    let output_file = BufWriter::new(File::create(output_path)?);
+    let mut encoder = Encoder::new(output_file, width, height);
+    encoder.set_color(match channels {
+        1 => ColorType::Grayscale,
+        2 => ColorType::GrayscaleAlpha,
+        4 => ColorType::Rgba,
+        _ => ColorType::Rgb,
+    });
+
+    let image_data = match &image.pixels {
+        // float is clamped to 16-bit
+        Pixels::Sixteen(_) | Pixels::Float(_) => {
+            encoder.set_depth(BitDepth::Sixteen);
+            flip_and_swap(&image.pixels.to_sixteen(), width as usize, channels).iter().flat_map(|sample| sample.to_be_bytes()).collect()
+        },
+        Pixels::Eight(pixels) => {
+            encoder.set_depth(BitDepth::Eight);
+            let samples = flip_and_swap(pixels, width as usize, channels);
+            // palette has no alpha here
+            let palette = if channels == 3 { palette_of(&samples) } else { None };
+            match palette {
+                Some((palette, indices)) => {
+                    encoder.set_color(ColorType::Indexed);
+                    encoder.set_palette(palette);
+                    indices
+                },
+                None => samples,
+            }
+        },
+    };
+
+    if image.pels_per_meter != (0, 0) {
+        let (xppu, yppu) = image.pels_per_meter;
+        encoder.set_pixel_dims(Some(PixelDimensions { xppu: xppu.max(0) as u32, yppu: yppu.max(0) as u32, unit: Unit::Meter }));
+    }
+
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+    writer.write_image_data(&image_data).map_err(encoding_error)?;
+    writer.finish().map_err(encoding_error)?;
+
+    Ok(())
+}
+
+// PNG is top-down RGB, internal buffer is bottom-up BGR,
+// so rows are reversed and R and B are swapped, the same both ways
+fn flip_and_swap<T: Copy>(samples: &[T], width: usize, channels: usize) -> Vec<T> {
+
+    let mut flipped = Vec::with_capacity(samples.len());
+    for row in samples.chunks(width * channels).rev() {
+        match channels {
+            1 | 2 => flipped.extend_from_slice(row),
+            _ => for pixel in row.chunks(channels) {
+                flipped.extend([pixel[2], pixel[1], pixel[0]]);
+                flipped.extend(pixel.get(3));
+            },
+        }
+    }
+
+    flipped
+}
+
+// (palette, index of each pixel), None if the image has more than 256 colors
+fn palette_of(rgb: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+
+    for pixel in rgb.chunks(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match colors.get(&color) {
+            Some(&index) => index,
+            None => {
+                if colors.len() == 256 { return None; }
+                let index = colors.len() as u8;
+                colors.insert(color, index);
+                palette.extend(color);
+                index
+            },
+        };
+        indices.push(index);
+    }
+
+    Some((palette, indices))
+}
+
+fn decoding_error(why: DecodingError) -> YamakagashiError {
+    match why {
+        DecodingError::IoError(why) => YamakagashiError::Io(why),
+        why => YamakagashiError::UnsupportedHeader(format!("PNG {why}")),
+    }
+}
+
+fn encoding_error(why: EncodingError) -> YamakagashiError {
+    match why {
+        EncodingError::IoError(why) => YamakagashiError::Io(why),
+        why => YamakagashiError::Io(io::Error::other(why)),
+    }
+}
+
+#[test]
+fn png_test() {
+    let path = std::env::temp_dir().join(format!("yamakagashi_png_test_{}.png", std::process::id()));
+    let size = (20u32, 17u32);
+
+    // few colors of BGR go through a palette, many are written as they are
+    for (number_of_colors, colors) in [(1u8, 256), (2, 256), (3, 7), (3, 256), (4, 256)] {
+        let pixels: Vec<u8> = (0..size.0 * size.1 * number_of_colors as u32).map(|i| (i * 37 % colors) as u8).collect();
+        let image = Image { size, number_of_colors, pixels: Pixels::Eight(pixels.clone()), top_down: false, pels_per_meter: (2835, 2835) };
+        png_writer(&path, &image).unwrap();
+        let read = png_opener(&path).unwrap();
+        assert_eq!((read.size, read.number_of_colors, read.pels_per_meter), (size, number_of_colors, (2835, 2835)));
+        assert!(matches!(read.pixels, Pixels::Eight(read) if read == pixels), "{number_of_colors} colors of {colors}");
+    }
+
+    let pixels: Vec<u16> = (0..size.0 * size.1 * 3).map(|i| (i * 4099 % 65536) as u16).collect();
+    png_writer(&path, &Image { size, number_of_colors: 3, pixels: Pixels::Sixteen(pixels.clone()), top_down: false, pels_per_meter: (0, 0) }).unwrap();
+    let read = png_opener(&path).unwrap();
+    assert_eq!(read.pels_per_meter, (0, 0));
+    assert!(matches!(read.pixels, Pixels::Sixteen(read) if read == pixels));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn malformed_png_test() {
+    let path = std::env::temp_dir().join(format!("yamakagashi_malformed_png_test_{}.png", std::process::id()));
+    png_writer(&path, &Image { size: (4, 4), number_of_colors: 1, pixels: Pixels::Eight(vec![128; 16]), top_down: false, pels_per_meter: (0, 0) }).unwrap();
+    let file = std::fs::read(&path).unwrap();
+
+    // signature, then a width of 0 in IHDR with its crc left as it was
+    for (at, byte) in [(1, b'Q'), (19, 0)] {
+        let mut broken = file.clone();
+        broken[at] = byte;
+        std::fs::write(&path, broken).unwrap();
+        assert!(matches!(png_opener(&path), Err(YamakagashiError::UnsupportedHeader(_))), "byte {at}");
+    }
+    std::fs::write(&path, &file[..40]).unwrap();
+    assert!(png_opener(&path).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,58 @@
+//! CodingOptions are the coding tools an image is coded with besides quality,
+//! the decoder has to be given the same options as the encoder, the container records them.
+use crate::color_transform::{check_colors, ColorTransform};
+use crate::error::{Result, YamakagashiError};
+use crate::subsampling::{check_subsampling, ChromaSubsampling};
+use crate::scan::ScanDirection;
+use crate::entropy::Entropy;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CodingOptions {
+    // keep the last plane of 2 or 4 planes (alpha) exactly, the others stay lossy
+    pub lossless_alpha: bool,
+    // decorrelate B, G and R of 3 or 4 colors image before coding
+    pub color_transform: ColorTransform,
+    // code the chroma planes of a color transform at reduced resolution
+    pub subsampling: ChromaSubsampling,
+    // find unit boundaries once per row for all planes of full resolution, and store them once
+    pub shared_units: bool,
+    // take unit boundaries and coeffs of a row from the row above where they fit
+    pub inter_row: bool,
+    // code smooth rectangles as 2-D patches, rows of units are left where no patch fits
+    pub patches: bool,
+    // run units along rows or along columns
+    pub scan: ScanDirection,
+    // backend packing the units into the stream
+    pub entropy: Entropy,
+    // store a coeff for every sample of a unit as files did before coefficient counts,
+    // otherwise a unit stores how many coeffs it has up to the last non-zero one and only those
+    pub trailing_zeros: bool,
+    // write unit sizes and coeff counts as varints of 7 bits a byte instead of u16, units then run longer than u16::MAX samples
+    pub varint_sizes: bool,
+}
+
+impl CodingOptions {
+
+    // refuse options the image has nothing to apply to
+    pub fn check(&self, number_of_colors: u8) -> Result<()> {
+        if self.lossless_alpha && number_of_colors != 2 && number_of_colors != 4 {
+            return Err(YamakagashiError::UnsupportedHeader(format!("{number_of_colors} colors image has no alpha to keep lossless")));
+        }
+        if self.patches && (self.shared_units || self.inter_row) {
+            return Err(YamakagashiError::UnsupportedHeader("patches have no rows across the plane to share units or predict from".to_string()));
+        }
+        self.entropy.check()?;
+        check_colors(self.color_transform, number_of_colors)?;
+        check_subsampling(self.subsampling, self.color_transform)
+    }
+
+    // longest unit a row is cut into, a u16 unit size can't count further
+    pub fn max_unit_size(&self) -> usize {
+        if self.varint_sizes { usize::MAX } else { u16::MAX as usize }
+    }
+
+    // planes coded into units, raw alpha is not
+    pub fn coded_colors(&self, number_of_colors: u8) -> u8 {
+        if self.lossless_alpha { number_of_colors - 1 } else { number_of_colors }
+    }
+}
@@ -0,0 +1,143 @@
+//! color transform decorrelates B, G and R before the planes are coded, and is undone after decoding
+//!
+//! YCoCg-R is lifting on integers, so it is exactly reversible, Co and Cg take one more bit than the samples.
+//! YCbCr is full range BT.601 like JFIF without the chroma offset, it rounds on the way and is meant for lossy coding.
+//! transformed planes are coded as wide samples which have a sign, float samples are transformed as they are.
+//! only the first 3 planes (B, G, R) are transformed, alpha is only widened.
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorTransform {
+    #[default]
+    None = 0,
+    YCoCgR = 1,
+    YCbCr = 2,
+}
+
+impl ColorTransform {
+
+    // names accepted by --color-transform
+    pub const NAMES: [&'static str; 3] = ["none", "ycocg-r", "ycbcr"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(ColorTransform::None),
+            "ycocg-r" | "ycocgr" => Some(ColorTransform::YCoCgR),
+            "ycbcr" => Some(ColorTransform::YCbCr),
+            _ => None,
+        }
+    }
+
+    // number recorded in the header
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(ColorTransform::None),
+            1 => Ok(ColorTransform::YCoCgR),
+            2 => Ok(ColorTransform::YCbCr),
+            _ => Err(YamakagashiError::UnsupportedHeader(format!("unknown color transform {id}"))),
+        }
+    }
+}
+
+// a color transform needs B, G and R
+pub fn check_colors(transform: ColorTransform, number_of_colors: u8) -> Result<()> {
+    if transform != ColorTransform::None && number_of_colors < 3 {
+        return Err(YamakagashiError::UnsupportedHeader(format!("{number_of_colors} colors image has no B, G and R to transform")));
+    }
+    Ok(())
+}
+
+// BGR(A) to Y, Co, Cg(, A) or Y, Cb, Cr(, A), chroma has a sign so they are wide samples
+pub fn forward_transform<S: Sample>(image: &[S], number_of_colors: u8, transform: ColorTransform) -> Vec<S::Wide> {
+
+    let mut transformed = Vec::with_capacity(image.len());
+    for pixel in image.chunks_exact(number_of_colors as usize) {
+        let [b, g, r] = [pixel[0], pixel[1], pixel[2]].map(Into::<f32>::into);
+        let planes = match transform {
+            ColorTransform::YCoCgR => ycocg_r_forward::<S>(b, g, r),
+            ColorTransform::YCbCr => ycbcr_forward(b, g, r),
+            ColorTransform::None => [b, g, r],
+        };
+        transformed.extend(planes.map(S::Wide::from_f32));
+        transformed.extend(pixel[3..].iter().map(|&alpha| S::Wide::from_f32(alpha.into())));
+    }
+
+    transformed
+}
+
+// Y, Co, Cg(, A) or Y, Cb, Cr(, A) back to BGR(A), saturated to the sample range
+pub fn inverse_transform<S: Sample>(transformed: &[S::Wide], number_of_colors: u8, transform: ColorTransform) -> Vec<S> {
+
+    let mut image = Vec::with_capacity(transformed.len());
+    for pixel in transformed.chunks_exact(number_of_colors as usize) {
+        let [y, c1, c2] = [pixel[0], pixel[1], pixel[2]].map(Into::<f32>::into);
+        let planes = match transform {
+            ColorTransform::YCoCgR => ycocg_r_inverse::<S>(y, c1, c2),
+            ColorTransform::YCbCr => ycbcr_inverse(y, c1, c2),
+            ColorTransform::None => [y, c1, c2],
+        };
+        image.extend(planes.map(S::from_f32));
+        image.extend(pixel[3..].iter().map(|&alpha| S::from_f32(alpha.into())));
+    }
+
+    image
+}
+
+// lifting rounds down on integer samples, so the inverse gets back exactly the same integers
+fn half<S: Sample>(value: f32) -> f32 {
+    if S::MAGNITUDE.is_some() { (value / 2.0).floor() } else { value / 2.0 }
+}
+
+fn ycocg_r_forward<S: Sample>(b: f32, g: f32, r: f32) -> [f32; 3] {
+
+    let co = r - b;
+    let t = b + half::<S>(co);
+    let cg = g - t;
+
+    [t + half::<S>(cg), co, cg]
+}
+
+fn ycocg_r_inverse<S: Sample>(y: f32, co: f32, cg: f32) -> [f32; 3] {
+
+    let t = y - half::<S>(cg);
+    let g = cg + t;
+    let b = t - half::<S>(co);
+
+    [b, g, b + co]
+}
+
+fn ycbcr_forward(b: f32, g: f32, r: f32) -> [f32; 3] {
+
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+
+    [y, (b - y) / 1.772, (r - y) / 1.402]
+}
+
+fn ycbcr_inverse(y: f32, cb: f32, cr: f32) -> [f32; 3] {
+
+    let b = y + 1.772 * cb;
+    let r = y + 1.402 * cr;
+    let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+
+    [b, g, r]
+}
+
+#[test]
+fn ycocg_r_test() {
+    // every combination of a coarse grid, saturated corners included, comes back exactly
+    let levels: Vec<u8> = (0..=255).step_by(15).collect();
+    let mut image: Vec<u8> = Vec::new();
+    for &b in &levels { for &g in &levels { for &r in &levels { image.extend([b, g, r]); } } }
+
+    let transformed = forward_transform(&image, 3, ColorTransform::YCoCgR);
+    assert_eq!(inverse_transform::<u8>(&transformed, 3, ColorTransform::YCoCgR), image);
+
+    // gray has no chroma, pure red has the most
+    let transformed = forward_transform(&[40u16, 40, 40, 7, 0, 0, 65535, 7], 4, ColorTransform::YCoCgR);
+    assert_eq!(transformed.into_iter().map(Into::<f32>::into).collect::<Vec<f32>>(), [40.0, 0.0, 0.0, 7.0, 16383.0, 65535.0, -32767.0, 7.0]);
+}
@@ -1,71 +1,207 @@
-mod unit_compression;
+pub mod unit_compression;
+pub mod patch_compression;
 use unit_compression::unit_compression;
+use patch_compression::{patch_compression, quadrants, root_rects, PatchNode, Rect, MIN_SIZE, PATCH_NODE, ROWS_NODE, SPLIT_NODE};
 use std::collections::LinkedList;
+use std::iter::{Skip, StepBy, Take};
+use std::slice::Iter;
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+use crate::color_transform::{forward_transform, ColorTransform};
+use crate::subsampling::downsample;
+use crate::coding_options::CodingOptions;
+use crate::scan::{transpose, ScanDirection};
+use crate::entropy::{packed_size, Entropy};
+use crate::parallel::{join, map_indices, map_slice};
+use crate::inter_row::{delta_against_above, same_unit_sizes, record_cost, COEFF_DELTA, SAME_UNIT_SIZES};
+
+// one color page is rows of units, a unit is (unit size, coeffs)
+pub type CompressedPage = Vec<LinkedList<(u32, Vec<u16>)>>;
+// samples of one color picked from interleaved image
+pub type Page<'a, S> = Take<StepBy<Skip<Iter<'a, S>>>>;
 
 // bitmap part of unit
+// options.lossless_alpha: the last plane (alpha of 2 or 4 planes) is appended as raw bytes instead of units
+// options.color_transform: B, G and R planes are turned into luma and chroma planes first
+// options.subsampling: chroma planes are coded at reduced resolution
+// options.shared_units: planes of full resolution are cut into units at the same points
+// options.inter_row: rows reuse unit boundaries of the row above and code units as deltas against it
+// options.patches: planes are cut into 2-D patches by quad-trees, rows of units are left where no patch fits
+// options.scan: columns codes the transposed image with the other options as they are
+// options.trailing_zeros: units store zeros after their last non-zero coeff instead of a count of their coeffs
+// options.varint_sizes: unit sizes and counts are varints instead of u16, and units are no longer cut at u16::MAX samples
 
-pub fn image_compression(image: &[u8], number_of_colors:u8, size:(u32, u32), quality: i32) -> Vec<u8> {
+pub fn image_compression<S: Sample>(image: &[S], number_of_colors:u8, size:(u32, u32), quality: i32, options: &CodingOptions) -> Result<Vec<u8>> {
 
-    let mut yamakagashi: Vec<Vec<LinkedList<(u16, Vec<u16>)>>> = vec![Vec::new();number_of_colors as usize];
+    options.check(number_of_colors)?;
+    if options.scan == ScanDirection::Columns {
+        let rows = CodingOptions { scan: ScanDirection::Rows, ..*options };
+        return image_compression(&transpose(image, number_of_colors, size), number_of_colors, options.scan.coded_size(size), quality, &rows);
+    }
+    let coded_colors = options.coded_colors(number_of_colors);
 
-    for (which_color, compressed_page ) in (0..number_of_colors).zip(yamakagashi.iter_mut()) {
-        let page = image.iter().skip(which_color as usize).step_by(number_of_colors as usize).take((size.0*size.1) as usize);
-            *compressed_page = page_compression(page, size, quality);
+    let mut yamakagashi_bytes = match options.color_transform {
+        ColorTransform::None => planes_compression(image, number_of_colors, coded_colors, size, quality, options)?,
+        _ => planes_compression(&forward_transform(image, number_of_colors, options.color_transform), number_of_colors, coded_colors, size, quality, options)?,
+    };
+    if options.lossless_alpha {
+        image.iter().skip(coded_colors as usize).step_by(number_of_colors as usize).take((size.0*size.1) as usize)
+            .for_each(|sample| sample.write_be(&mut yamakagashi_bytes));
     }
 
-    organize(&yamakagashi, ((number_of_colors as u32)*size.0*size.1) as usize)
+    Ok(yamakagashi_bytes)
 }
 
-fn page_compression(page: std::iter::Take<std::iter::StepBy<std::iter::Skip<std::slice::Iter<u8>>>>, size:(u32, u32), quality: i32) -> Vec<LinkedList<(u16, Vec<u16>)>> {
+// the first coded_colors planes to units, organized into bytes
+fn planes_compression<S: Sample>(image: &[S], number_of_colors:u8, coded_colors:u8, size:(u32, u32), quality: i32, options: &CodingOptions) -> Result<Vec<u8>> {
+
+    let plane_sizes: Vec<(u32, u32)> = (0..coded_colors as usize).map(|which_color| options.subsampling.plane_size(size, which_color)).collect();
+    // subsampled planes are picked out of the image first
+    let subsampled: Vec<Option<Vec<S>>> = plane_sizes.iter().enumerate()
+        .map(|(which_color, &plane_size)| (plane_size != size).then(|| downsample(image, number_of_colors, which_color, size, options.subsampling))).collect();
+    let pages: Vec<Page<S>> = subsampled.iter().zip(&plane_sizes).enumerate().map(|(which_color, (plane, &plane_size))| {
+        let (source, offset, stride) = match plane {
+            Some(plane) => (&plane[..], 0, 1),
+            None => (image, which_color, number_of_colors as usize),
+        };
+        source.iter().skip(offset).step_by(stride).take((plane_size.0*plane_size.1) as usize)
+    }).collect();
+
+    if options.patches {
+        let patch_pages: Vec<Vec<PatchNode>> = map_indices(pages.len(), |i| patch_page_compression(&pages[i], plane_sizes[i], quality)).into_iter().collect::<Result<_>>()?;
+        return Ok(organize_patches(&patch_pages, S::MAGNITUDE.is_none() as usize, options.trailing_zeros, options.varint_sizes));
+    }
+
+    // planes of full resolution can share unit boundaries
+    let shared: Vec<bool> = plane_sizes.iter().map(|&plane_size| options.shared_units && plane_size == size).collect();
+    // luma carries the edges of a color transform, otherwise every plane has its say
+    let shared_pages: Vec<Page<S>> = pages.iter().zip(&shared).filter(|(_, &shared)| shared).map(|(page, _)| page.clone())
+        .take(if options.color_transform == ColorTransform::None { usize::MAX } else { 1 }).collect();
+    let shared_turning_points = rows_turning_points(&shared_pages, size, options.inter_row, options.max_unit_size());
+
+    let yamakagashi: Vec<CompressedPage> = map_indices(pages.len(), |i| {
+        let (page, plane_size) = (&pages[i], plane_sizes[i]);
+        if shared[i] {
+            page_compression(page.clone(), plane_size, quality, &shared_turning_points)
+        } else {
+            page_compression(page.clone(), plane_size, quality, &rows_turning_points(std::slice::from_ref(page), plane_size, options.inter_row, options.max_unit_size()))
+        }
+    }).into_iter().collect::<Result<_>>()?;
+
+    let subpixels = plane_sizes.iter().map(|&(width, height)| (width*height) as usize).sum();
+    Ok(organize::<S>(&yamakagashi, subpixels, &shared, options.inter_row, options.entropy, options.trailing_zeros, options.varint_sizes))
+}
+
+// turning points of every row, found on all pages at once, empty if there is no page
+// inter_row: a row takes the turning points of the row above when cutting there leaves nothing to cut
+// max_unit_size: units longer than that are cut anyway
+fn rows_turning_points<S: Sample>(pages: &[Page<S>], size:(u32, u32), inter_row: bool, max_unit_size: usize) -> Vec<LinkedList<usize>> {
+
+    if pages.is_empty() { return Vec::new(); }
+    let rows_of = |i: usize| -> Vec<_> { pages.iter().map(|page| page.clone().skip(size.0 as usize * i).take(size.0 as usize)).collect() };
+    // rows don't wait for each other unless they lean on the row above
+    if !inter_row {
+        return map_indices(size.1 as usize, |i| turning_points_of(&rows_of(i), max_unit_size));
+    }
 
-    let mut compressed_page: Vec<LinkedList<(u16, Vec<u16>)>> = vec![LinkedList::new(); size.1 as usize];
-    
-    // let page_clone = page.clone();
-    // let mut unit_count = 0;
     let mut rows_turning_points: Vec<LinkedList<usize>> = vec![LinkedList::new(); size.1 as usize];
-    for (i,turning_points) in rows_turning_points.iter_mut().enumerate() {
-        *turning_points = turning_points_of(page.clone().skip(size.0 as usize * i).take(size.0 as usize));
-        // unit_count += turning_points.len();
-    }
-    // println!("{:?}", rows_turning_points.iter().map(|a| a.len()).collect::<Vec<usize>>());
-    // println!("unit: {unit_count}");
-
-    for (i, (turning_points, compressed_row)) in rows_turning_points.iter().zip(compressed_page.iter_mut()).enumerate() {
-        let mut pre_point: usize = 0;
-        for &turning_point in turning_points {
-            // if pre_point > turning_point {panic!("pre_point is bigger than turning_pint, pre_point:{pre_point}, turning_point:{turning_point}")}
-            let unit = page.clone().skip(size.0 as usize*i+pre_point).take(turning_point-pre_point);
-            let coeffs: Vec<u16> = unit_compression(unit, quality);
-            compressed_row.push_back(((turning_point-pre_point) as u16, coeffs));
-            pre_point = turning_point;
+    for i in 0..rows_turning_points.len() {
+        let rows = rows_of(i);
+        let above = i.checked_sub(1).map(|above| rows_turning_points[above].clone());
+        rows_turning_points[i] = match above {
+            Some(above) if cut_points_of(&rows, &above, max_unit_size) == above => above,
+            _ => turning_points_of(&rows, max_unit_size),
+        };
+    }
+
+    rows_turning_points
+}
+
+fn page_compression<S: Sample>(page: Page<S>, size:(u32, u32), quality: i32, rows_turning_points: &[LinkedList<usize>]) -> Result<CompressedPage> {
+
+    map_slice(&rows_turning_points.iter().enumerate().collect::<Vec<_>>(), |&(i, turning_points)| {
+        row_compression(&page, i, size.0 as usize*i, size.0 as usize, turning_points, quality)
+    }).into_iter().collect()
+}
+
+// units of length samples from offset of the page, cut at turning points, row is for error report
+fn row_compression<S: Sample>(page: &Page<S>, row: usize, offset: usize, length: usize, turning_points: &LinkedList<usize>, quality: i32) -> Result<LinkedList<(u32, Vec<u16>)>> {
+
+    // (start, end) of every unit, each unit is fitted on its own
+    let units: Vec<(usize, usize)> = [0].into_iter().chain(turning_points.iter().copied()).zip(turning_points.iter().copied().chain([length])).collect();
+    let compressed_row: LinkedList<(u32, Vec<u16>)> = map_slice(&units, |&(pre_point, turning_point)| {
+        let unit = page.clone().skip(offset+pre_point).take(turning_point-pre_point);
+        unit_compression(unit, quality).map(|coeffs| ((turning_point-pre_point) as u32, coeffs))
+    }).into_iter().collect::<Result<_>>()?;
+
+    let row_size = compressed_row.iter().map(|a| a.0 as usize).sum::<usize>();
+    if row_size != length {
+        return Err(YamakagashiError::UnitSizeMismatch { row, expected: length as u32, actual: row_size as u32 });
+    }
+    Ok(compressed_row)
+}
+
+// quad-trees of the root squares of a page, see patch_compression
+fn patch_page_compression<S: Sample>(page: &Page<S>, size:(u32, u32), quality: i32) -> Result<Vec<PatchNode>> {
+    map_slice(&root_rects(size), |&rect| node_compression(page, size.0, rect, quality)).into_iter().collect()
+}
+
+// a patch if one fits, else the quadrants, and rows if no patch fits anywhere in them
+fn node_compression<S: Sample>(page: &Page<S>, page_width: u32, rect: Rect, quality: i32) -> Result<PatchNode> {
+
+    if let Some((degree, coeffs)) = patch_compression(page, page_width, rect, quality)? {
+        return Ok(PatchNode::Patch { degree, coeffs });
+    }
+
+    let (x, y, width, height) = rect;
+    if width > MIN_SIZE || height > MIN_SIZE {
+        let quadrants: Vec<PatchNode> = quadrants(rect).into_iter().map(|quadrant| node_compression(page, page_width, quadrant, quality)).collect::<Result<_>>()?;
+        if !quadrants.iter().all(|quadrant| matches!(quadrant, PatchNode::Rows(_))) {
+            return Ok(PatchNode::Split(quadrants));
         }
-        
-        let unit = page.clone().skip(size.0 as usize*i+pre_point).take(size.0 as usize-pre_point);
-        let coeffs: Vec<u16> = unit_compression(unit, quality);
-        compressed_row.push_back(((size.0 as usize-pre_point) as u16, coeffs));
-        
-        assert_eq!(compressed_row.iter().map(|a| a.0 as u32).sum::<u32>(), size.0);
     }
-    // organize conpressed_page
-    
-    compressed_page
+
+    // units run as long as the rect is wide
+    let mut rows: CompressedPage = Vec::with_capacity(height as usize);
+    for row in y..y + height {
+        let offset = (row * page_width + x) as usize;
+        // a rect is narrower than any unit size can count
+        let turning_points = turning_points_of(&[page.clone().skip(offset).take(width as usize)], usize::MAX);
+        rows.push(row_compression(page, row as usize, offset, width as usize, &turning_points, quality)?);
+    }
+
+    Ok(PatchNode::Rows(rows))
 }
 
-fn turning_points_of<'a, I>(row: I) -> LinkedList<usize> where I: Iterator<Item = &'a u8> + ExactSizeIterator + Clone {
+// rows of several planes are cut where any of them turns
+fn turning_points_of<'a, S: Sample, I>(rows: &[I], max_unit_size: usize) -> LinkedList<usize> where I: Iterator<Item = &'a S> + ExactSizeIterator + Clone {
+    cut_points_of(rows, &LinkedList::new(), max_unit_size)
+}
+
+// turning points with the rows cut at forced points first, the prediction starts again at each of them
+// max_unit_size: a unit reaching that many samples is cut as if forced
+fn cut_points_of<'a, S: Sample, I>(rows: &[I], forced: &LinkedList<usize>, max_unit_size: usize) -> LinkedList<usize> where I: Iterator<Item = &'a S> + ExactSizeIterator + Clone {
 
-    let n: usize = row.len();
-    let s_row:Vec<i32> = row.clone().scan(0i32, |acc, &x| { *acc += x as i32; Some(*acc) }).collect();
-    let l_row:Vec<i32> = row.clone().enumerate().scan(0i32, |acc, (i, x)| {*acc += (i as i32+1)*(*x as i32); Some(*acc)}).collect();
+    let n: usize = rows[0].len();
+    // i64, a long row of 16-bit samples overflows i32
+    let rows_sums: Vec<(Vec<i64>, Vec<i64>)> = rows.iter().map(|row| {
+        let s_row:Vec<i64> = row.clone().scan(0i64, |acc, &x| { *acc += x.level(); Some(*acc) }).collect();
+        let l_row:Vec<i64> = row.clone().enumerate().scan(0i64, |acc, (i, &x)| {*acc += (i as i64+1)*x.level(); Some(*acc)}).collect();
+        (s_row, l_row)
+    }).collect();
+    let rows_levels: Vec<Vec<i64>> = rows.iter().map(|row| row.clone().map(|x| x.level()).collect()).collect();
     let mut prepoint = 0usize;
     let mut turning_points: LinkedList<usize> = LinkedList::new();
+    let mut forced = forced.iter().peekable();
 
     const MAX_WINDOW_WIDTH: usize = 50;
-    const DIFFERENCE: i64 = 5;
+    // 5 out of 8-bit range, scaled for wider samples
+    let difference: i64 = 5 * S::LEVELS / 256;
 
-    for (point, ele) in (0..n).zip(row) {
+    for point in 0..n {
         
         if point == 0 { continue; }
-        if point == u16::MAX as usize + prepoint {
+        if forced.next_if_eq(&&point).is_some() || point - prepoint == max_unit_size {
             turning_points.push_back(point);
             prepoint = point;
             continue;
@@ -74,15 +210,16 @@ fn turning_points_of<'a, I>(row: I) -> LinkedList<usize> where I: Iterator<Item
         let width = (point - prepoint).min(MAX_WINDOW_WIDTH);
         if width == 1 { continue; }
 
+        let turns = rows_sums.iter().zip(&rows_levels).any(|((s_row, l_row), levels)| {
+            let sum = if point-width == 0 { s_row[point-1] } else { s_row[point-1] - s_row[point-width-1] };
+            let bias_sum = if point-width == 0 { l_row[point-1] } else { l_row[point-1] - l_row[point-width-1] } - sum*(width as i64+1)/2 - sum*(point-width) as i64;
+            let sqsum = width*(width*width-1)/6;
+            let diff_coeff = bias_sum*2 / sqsum as i64;
 
-        let sum = if point-width == 0 { s_row[point-1] - 0 } else { s_row[point-1] - s_row[point-width-1] } as i64;
-        let bias_sum = if point-width == 0 { l_row[point-1] - 0 } else { l_row[point-1] - l_row[point-width-1] } as i64 - sum*(width as i64+1)/2 - sum*(point-width) as i64;
-        let sqsum = width*(width*width-1)/6;
-        let diff_coeff = bias_sum*2 / sqsum as i64;
-
-        
-        let prediction = sum/width as i64 + diff_coeff*(width as i64+1)/2;
-        if (*ele as i64 - prediction).abs() > DIFFERENCE {
+            let prediction = sum/width as i64 + diff_coeff*(width as i64+1)/2;
+            (levels[point] - prediction).abs() > difference
+        });
+        if turns {
             turning_points.push_back(point);
             prepoint = point;
         }
@@ -91,41 +228,161 @@ fn turning_points_of<'a, I>(row: I) -> LinkedList<usize> where I: Iterator<Item
     turning_points
 }
 
-fn organize(yamakagashi: &Vec<Vec<LinkedList<(u16, Vec<u16>)>>>, subpixels: usize) -> Vec<u8> {
+// shared: unit sizes of the planes sharing them are written once ahead of all planes
+// inter_row: u16 row modes of all rows come first, unit sizes same as the row above are not written,
+// co-located units are written as deltas when they look cheaper and the entropy backend agrees, against the row above as the decoder rebuilds it
+fn organize<S: Sample>(yamakagashi: &[CompressedPage], subpixels: usize, shared: &[bool], inter_row: bool, entropy: Entropy, trailing_zeros: bool, varint_sizes: bool) -> Vec<u8> {
+
+    // magnitude of a float unit is one more u16
+    let header_size = S::MAGNITUDE.is_none() as usize;
 
     const COEFF_BYTES_SIZE: usize = 2; // coeff u16 is 2bytes
-    const UNIT_BYTES_SIZE: usize = 2; // unit size u16 is 2bytes
+    const UNIT_BYTES_SIZE: usize = 2; // unit size u16 is 2bytes, a varint is mostly shorter
 
 
     let count = COEFF_BYTES_SIZE*subpixels // sum of all subpixels as bytes
         + UNIT_BYTES_SIZE * yamakagashi.iter().map(|page| page.iter().map(|row| row.len()).sum::<usize>()).sum::<usize>(); // every unit has u16(2 bytes) unit size value
     let mut yamakagashi_bytes: Vec<u8> = Vec::with_capacity(count);
+    // kept out of the rows, the backend matches the rows better without them
+    let mut row_modes: Vec<u16> = Vec::new();
 
-    for color_page in yamakagashi {
-        for row in color_page {
-            for (unit_size, coeffs) in row {
-                yamakagashi_bytes.extend(unit_size.to_be_bytes());
-                coeffs.iter().for_each(|coeff| yamakagashi_bytes.extend(coeff.to_be_bytes()));
+    if let Some((shared_page, _)) = yamakagashi.iter().zip(shared).find(|(_, &shared)| shared) {
+        for (i, row) in shared_page.iter().enumerate() {
+            if inter_row {
+                let same = i > 0 && same_unit_sizes(row, &shared_page[i-1]);
+                row_modes.push(if same { SAME_UNIT_SIZES } else { 0 });
+                if same { continue; }
             }
+            row.iter().for_each(|&(unit_size, _)| write_length(&mut yamakagashi_bytes, unit_size, varint_sizes));
+        }
+    }
+
+    let pages_bytes = map_indices(yamakagashi.len(), |i| {
+        let (color_page, shared) = (&yamakagashi[i], shared[i]);
+        if inter_row {
+            // deltas pay on smooth pictures, flat and repeated ones the backend packs better as they are
+            // the backend at a fast level tells them apart nearly as well, at a fraction of packing each page twice
+            let trial = |deltas: bool| { let bytes = page_bytes(color_page, shared, true, deltas, header_size, trailing_zeros, varint_sizes); (page_size(entropy.fast(), &bytes), bytes) };
+            let ((with_size, with_deltas), (without_size, without_deltas)) = join(|| trial(true), || trial(false));
+            if without_size <= with_size { without_deltas } else { with_deltas }
+        } else {
+            page_bytes(color_page, shared, false, false, header_size, trailing_zeros, varint_sizes)
+        }
+    });
+    for (page_row_modes, page_bytes) in pages_bytes {
+        row_modes.extend(page_row_modes);
+        yamakagashi_bytes.extend(page_bytes);
+    }
+
+    row_modes.iter().flat_map(|row_mode| row_mode.to_be_bytes()).chain(yamakagashi_bytes).collect()
+}
+
+// every node starts with its tag, a split is followed by its quadrants, rows by their units with unit sizes
+fn organize_patches(patch_pages: &[Vec<PatchNode>], header_size: usize, trailing_zeros: bool, varint_sizes: bool) -> Vec<u8> {
+
+    fn organize_node(node: &PatchNode, yamakagashi_bytes: &mut Vec<u8>, header_size: usize, trailing_zeros: bool, varint_sizes: bool) {
+        match node {
+            PatchNode::Split(quadrants) => {
+                yamakagashi_bytes.extend(SPLIT_NODE.to_be_bytes());
+                quadrants.iter().for_each(|quadrant| organize_node(quadrant, yamakagashi_bytes, header_size, trailing_zeros, varint_sizes));
+            },
+            PatchNode::Rows(rows) => {
+                yamakagashi_bytes.extend(ROWS_NODE.to_be_bytes());
+                for (unit_size, coeffs) in rows.iter().flatten() {
+                    write_length(yamakagashi_bytes, *unit_size, varint_sizes);
+                    write_coeffs(yamakagashi_bytes, coeffs, header_size, trailing_zeros, varint_sizes);
+                }
+            },
+            PatchNode::Patch { degree, coeffs } => {
+                yamakagashi_bytes.extend((PATCH_NODE + degree).to_be_bytes());
+                write_coeffs(yamakagashi_bytes, coeffs, header_size, trailing_zeros, varint_sizes);
+            },
         }
     }
 
+    let mut yamakagashi_bytes: Vec<u8> = Vec::new();
+    patch_pages.iter().flatten().for_each(|node| organize_node(node, &mut yamakagashi_bytes, header_size, trailing_zeros, varint_sizes));
+
     yamakagashi_bytes
 }
 
+// (row modes, bytes) of one page, deltas: co-located units may be written as deltas
+fn page_bytes(color_page: &CompressedPage, shared: bool, inter_row: bool, deltas: bool, header_size: usize, trailing_zeros: bool, varint_sizes: bool) -> (Vec<u16>, Vec<u8>) {
+
+    let mut row_modes: Vec<u16> = Vec::new();
+    let mut page_bytes: Vec<u8> = Vec::new();
+
+    // deltas round, so the next row is predicted from the rebuilt row
+    let mut rebuilt_above: Option<LinkedList<(u32, Vec<u16>)>> = None;
+    for row in color_page {
+        let mut row_mode = 0u16;
+        let mut delta_row = None;
+        if let Some(above) = &rebuilt_above {
+            if !shared && same_unit_sizes(row, above) { row_mode |= SAME_UNIT_SIZES; }
+            let delta = deltas.then(|| delta_against_above(row, above, header_size, true));
+            if let Some(delta) = delta.filter(|delta| record_cost(delta) < record_cost(row)) {
+                row_mode |= COEFF_DELTA;
+                delta_row = Some(delta);
+            }
+        }
+        if inter_row {
+            row_modes.push(row_mode);
+            rebuilt_above = Some(match (&delta_row, &rebuilt_above) {
+                (Some(delta), Some(above)) => delta_against_above(delta, above, header_size, false),
+                _ => row.clone(),
+            });
+        }
+
+        for (unit_size, coeffs) in delta_row.as_ref().unwrap_or(row) {
+            if !shared && row_mode & SAME_UNIT_SIZES == 0 { write_length(&mut page_bytes, *unit_size, varint_sizes); }
+            write_coeffs(&mut page_bytes, coeffs, header_size, trailing_zeros, varint_sizes);
+        }
+    }
+
+    (row_modes, page_bytes)
+}
+
+// coeffs of a unit or patch, the header of a float one is always written
+// trailing_zeros: every coeff, otherwise the count of coeffs up to the last non-zero one ahead of the header and those
+fn write_coeffs(yamakagashi_bytes: &mut Vec<u8>, coeffs: &[u16], header_size: usize, trailing_zeros: bool, varint_sizes: bool) {
+
+    let count = if trailing_zeros { coeffs.len() - header_size } else { coeffs[header_size..].iter().rposition(|&coeff| coeff != 0).map_or(0, |last| last + 1) };
+    if !trailing_zeros { write_length(yamakagashi_bytes, count as u32, varint_sizes); }
+    coeffs[..header_size + count].iter().for_each(|coeff| yamakagashi_bytes.extend(coeff.to_be_bytes()));
+}
+
+// a unit size or coeff count
+// varint_sizes: 7 bits a byte from the top, the high bit tells another byte follows, otherwise a big-endian u16
+fn write_length(yamakagashi_bytes: &mut Vec<u8>, length: u32, varint_sizes: bool) {
+
+    if !varint_sizes {
+        yamakagashi_bytes.extend((length as u16).to_be_bytes());
+        return;
+    }
+    let groups = (32 - length.leading_zeros()).div_ceil(7).max(1);
+    yamakagashi_bytes.extend((0..groups).rev().map(|group| (length >> (7 * group)) as u8 & 0x7F | if group > 0 { 0x80 } else { 0 }));
+}
+
+// bytes of a page after the entropy backend, to choose between ways of writing it
+fn page_size(entropy: Entropy, (row_modes, page_bytes): &(Vec<u16>, Vec<u8>)) -> usize {
+
+    let bytes: Vec<u8> = row_modes.iter().flat_map(|row_mode| row_mode.to_be_bytes()).chain(page_bytes.iter().copied()).collect();
+    packed_size(entropy, &bytes)
+}
+
 #[test]
 fn points_test(){
     // let row = vec![100,100,100,100,100,100,120,140,160,180,200,220,240,100,100,100,100,100];
-    let row = vec!
+    let row: Vec<u8> = vec!
     [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 189, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
     ;
-    let points = turning_points_of(row.iter());
+    let points = turning_points_of(&[row.iter()], u16::MAX as usize);
     println!("{:?}", points);
     let mut pre_point = 0;
     println!("cut row:");
     for i in points {
-        print!("{:>4?} ", row.iter().skip(pre_point).take(i-pre_point).map(|a| *a).collect::<Vec<u8>>());
+        print!("{:>4?} ", row.iter().skip(pre_point).take(i-pre_point).copied().collect::<Vec<u8>>());
         pre_point = i;
     }
-    print!("{:>4?} ", row.iter().skip(pre_point).take(row.len()-pre_point).map(|a| *a).collect::<Vec<u8>>());
+    print!("{:>4?} ", row.iter().skip(pre_point).take(row.len()-pre_point).copied().collect::<Vec<u8>>());
 }
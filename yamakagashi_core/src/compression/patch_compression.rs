@@ -0,0 +1,217 @@
+//! patch_compression fits one 2-D unit (patch) to a rectangle of a page
+//!
+//! a patch is a sum of c_ij p_i(x) q_j(y) with total degree i+j up to MAX_PATCH_DEGREE,
+//! p_i and q_j are the monic polynomials orthogonal on the points of the rectangle,
+//! so least squares is one projection per term and nothing has to be solved.
+//! x and y are centered like the x of unit_compression, a coefficient is forecast from its degrees like a unit does.
+//!
+//! the page is cut into ROOT_SIZE squares, and a square is cut into 4 (quad-tree) while no patch fits it,
+//! a patch fits when it meets the quality and no sample is farther from it than a turning point would allow.
+//! a rectangle where nothing fits down to MIN_SIZE is coded as rows of 1-D units.
+use crate::my_float::MyFp48;
+use crate::my_vector::{VecTool, HadamardProduct};
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+use super::{CompressedPage, Page};
+use std::ops::Range;
+
+pub const ROOT_SIZE: u32 = 64;
+pub const MIN_SIZE: u32 = 8;
+pub const MAX_PATCH_DEGREE: u16 = 4;
+
+// node tags of the stream, a patch node is PATCH_NODE + its degree
+pub const SPLIT_NODE: u16 = 0;
+pub const ROWS_NODE: u16 = 1;
+pub const PATCH_NODE: u16 = 2;
+
+// (x, y, width, height) in samples of the page
+pub type Rect = (u32, u32, u32, u32);
+
+// quad-tree of one root square
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchNode {
+    // the quadrants of quadrants(), empty ones left out
+    Split(Vec<PatchNode>),
+    // rows of 1-D units, a unit is (unit size, coeffs)
+    Rows(CompressedPage),
+    // coeffs are the magnitude of a float patch and the records of patch_terms
+    Patch { degree: u16, coeffs: Vec<u16> },
+}
+
+// root squares of a page, row by row, cut short at the right and the top
+pub fn root_rects(size: (u32, u32)) -> Vec<Rect> {
+    (0..size.1).step_by(ROOT_SIZE as usize)
+        .flat_map(|y| (0..size.0).step_by(ROOT_SIZE as usize).map(move |x| (x, y, ROOT_SIZE.min(size.0 - x), ROOT_SIZE.min(size.1 - y))))
+        .collect()
+}
+
+// the 4 quadrants, the lower ones take the odd sample, empty ones are left out
+pub fn quadrants((x, y, width, height): Rect) -> Vec<Rect> {
+    let (left, bottom) = (width.div_ceil(2), height.div_ceil(2));
+    [(x, y, left, bottom), (x + left, y, width - left, bottom), (x, y + bottom, left, height - bottom), (x + left, y + bottom, width - left, height - bottom)]
+        .into_iter().filter(|&(_, _, width, height)| width > 0 && height > 0).collect()
+}
+
+// (i, j) of the terms of a patch in stream order, a degree the rectangle has too few points for is left out
+pub fn patch_terms(degree: u16, width: u32, height: u32) -> Vec<(usize, usize)> {
+    (0..=degree as usize).flat_map(|total| (0..=total).rev().map(move |i| (i, total - i)))
+        .filter(|&(i, j)| i < width as usize && j < height as usize).collect()
+}
+
+// p_0..=p_degree orthogonal on n centered points, evaluated at points
+// p_k+1 = x p_k - k^2 (n^2 - k^2) / (4 (4k^2 - 1)) p_k-1
+pub fn orthogonal_polynomials(n: u32, points: &[MyFp48], degree: usize) -> Vec<Vec<MyFp48>> {
+
+    let mut polynomials: Vec<Vec<MyFp48>> = vec![vec![MyFp48::ONE; points.len()]];
+    if degree > 0 { polynomials.push(points.to_vec()); }
+    for k in 1..degree {
+        let beta = MyFp48::new((k * k) as f32 * (n as f32 * n as f32 - (k * k) as f32) / (4.0 * (4 * k * k - 1) as f32));
+        let mut next = polynomials[k].clone();
+        next.hadamard_product(points);
+        next.iter_mut().zip(&polynomials[k - 1]).for_each(|(a, &b)| *a -= beta * b);
+        polynomials.push(next);
+    }
+
+    polynomials
+}
+
+// centered points of n samples, scale points per sample each in the middle of its part
+pub fn patch_points(n: u32, scale: usize) -> Vec<MyFp48> {
+    (0..n as usize * scale).map(|i| MyFp48::new(((2 * i + 1) as f32 / scale as f32 - n as f32) / 2.0)).collect()
+}
+
+// forecast exponent of term (i, j), as unit_compression does for one axis
+pub fn term_forecast(i: usize, j: usize, width: u32, height: u32) -> i32 {
+    (i as f64 * ((width as f64).log2() - 1.0) + j as f64 * ((height as f64).log2() - 1.0)).trunc() as i32
+}
+
+// values of a patch, height rows of width*scale, coefficient values are given for patch_terms in order
+pub fn evaluate_patch(width: u32, height: u32, scale: usize, terms: &[(usize, usize)], values: &[MyFp48]) -> Vec<MyFp48> {
+    evaluate_patch_window(width, height, scale, terms, values, 0..width as usize * scale, 0..height as usize)
+}
+
+// the same values in columns of rows only, the others are not evaluated
+pub fn evaluate_patch_window(width: u32, height: u32, scale: usize, terms: &[(usize, usize)], values: &[MyFp48], columns: Range<usize>, rows: Range<usize>) -> Vec<MyFp48> {
+
+    let degree = terms.iter().map(|&(i, j)| i.max(j)).max().unwrap_or(0);
+    let p = orthogonal_polynomials(width, &patch_points(width, scale)[columns.clone()], degree);
+    let q = orthogonal_polynomials(height, &patch_points(height, 1)[rows.clone()], degree);
+
+    // one row polynomial for each degree of y
+    let mut row_polynomials = vec![vec![MyFp48::ZERO; columns.len()]; degree + 1];
+    for (&(i, j), &value) in terms.iter().zip(values) {
+        row_polynomials[j].iter_mut().zip(&p[i]).for_each(|(a, &b)| *a += value * b);
+    }
+
+    let mut patch = Vec::with_capacity(columns.len() * rows.len());
+    for y in 0..rows.len() {
+        let mut row = vec![MyFp48::ZERO; columns.len()];
+        for (row_polynomial, q_j) in row_polynomials.iter().zip(&q) {
+            row.iter_mut().zip(row_polynomial).for_each(|(a, &b)| *a += q_j[y] * b);
+        }
+        patch.extend(row);
+    }
+
+    patch
+}
+
+// records of the patch of the lowest degree which fits rect, None if none does
+pub fn patch_compression<S: Sample>(page: &Page<S>, page_width: u32, (x, y, width, height): Rect, quality: i32) -> Result<Option<(u16, Vec<u16>)>> {
+
+    let rows: Vec<Vec<S>> = (0..height).map(|row| page.clone().skip(((y + row) * page_width + x) as usize).take(width as usize).copied().collect()).collect();
+    let b: Vec<Vec<MyFp48>> = rows.iter().map(|row| row.iter().map(|&sample| MyFp48::new(sample.into())).collect()).collect();
+    let b_sq_norm: MyFp48 = b.iter().map(|row| row.sq_norm()).sum();
+    // the distance a turning point is cut at
+    let difference: i64 = 5 * S::LEVELS / 256;
+
+    let max_degree = MAX_PATCH_DEGREE as usize;
+    let p = orthogonal_polynomials(width, &patch_points(width, 1), max_degree);
+    let q = orthogonal_polynomials(height, &patch_points(height, 1), max_degree);
+    let (p_sq_norms, q_sq_norms): (Vec<MyFp48>, Vec<MyFp48>) = (p.iter().map(|p_i| p_i.sq_norm()).collect(), q.iter().map(|q_j| q_j.sq_norm()).collect());
+    // projection of every row on every p_i
+    let row_dots: Vec<Vec<MyFp48>> = b.iter().map(|row| p.iter().map(|p_i| row.dot(p_i.iter())).collect()).collect();
+
+    // a patch of a degree near the size of the rect only threads the samples, and swings between them
+    let max_fit_degree = MAX_PATCH_DEGREE.min((width.min(height) as u16 - 1) / 2);
+    for degree in 0..=max_fit_degree {
+
+        let terms = patch_terms(degree, width, height);
+        let values: Vec<MyFp48> = terms.iter().map(|&(i, j)| {
+            let dot: MyFp48 = row_dots.iter().zip(&q[j]).map(|(row_dot, &q_j)| row_dot[i] * q_j).sum();
+            dot / (p_sq_norms[i] * q_sq_norms[j])
+        }).collect();
+
+        // fitted energy, the terms are orthogonal
+        let fitted: MyFp48 = terms.iter().zip(&values).map(|(&(i, j), &value)| value * value * p_sq_norms[i] * q_sq_norms[j]).sum();
+        if fitted <= b_sq_norm * MyFp48::new(quality as f32 / 100.0) { continue; }
+
+        let coeffs = round_to_records::<S>(&terms, &values, width, height);
+        let decoded = evaluate_patch(width, height, 1, &terms, &record_values::<S>(&terms, &coeffs, width, height)?);
+        let fits = decoded.chunks(width as usize).zip(&rows).all(|(decoded_row, row)| decoded_row.iter().zip(row).all(|(&value, &sample)| {
+            S::from_my_fp48(value).is_ok_and(|value| (value.level() - sample.level()).abs() <= difference)
+        }));
+        if fits { return Ok(Some((degree, coeffs))); }
+    }
+
+    Ok(None)
+}
+
+// records of values, a float patch puts its magnitude ahead of them
+fn round_to_records<S: Sample>(terms: &[(usize, usize)], values: &[MyFp48], width: u32, height: u32) -> Vec<u16> {
+
+    let mut coeffs = Vec::with_capacity(terms.len() + 1);
+    let magnitude = match S::MAGNITUDE {
+        Some(magnitude) => magnitude,
+        None => {
+            // magnitude which puts the biggest record of the patch at exponent 0
+            let magnitude = terms.iter().zip(values).filter(|(_, value)| !value.is_zero())
+                .map(|(&(i, j), value)| value.exponent() + term_forecast(i, j, width, height))
+                .max().unwrap_or(0).clamp(i16::MIN as i32, i16::MAX as i32);
+            coeffs.push(magnitude as i16 as u16);
+            magnitude
+        },
+    };
+
+    coeffs.extend(terms.iter().zip(values).map(|(&(i, j), &value)| match value.to_record_bytes_with_forecast(term_forecast(i, j, width, height) - magnitude) {
+        Ok(record) => record,
+        Err("can't express f16, because of this MyFp48 abs is too small") => 0x0000,
+        Err(_) => if value.sign() == 1 { 0x7FFF } else { 0xFFFF },
+    }));
+
+    coeffs
+}
+
+// coefficient values of the records of a patch, the inverse of round_to_records
+// a record forecast out of the range of MyFp48 is CoefficientOverflow
+pub fn record_values<S: Sample>(terms: &[(usize, usize)], coeffs: &[u16], width: u32, height: u32) -> Result<Vec<MyFp48>> {
+
+    let (magnitude, records) = match S::MAGNITUDE {
+        Some(magnitude) => (magnitude, coeffs),
+        None => (coeffs[0] as i16 as i32, &coeffs[1..]),
+    };
+
+    terms.iter().zip(records).map(|(&(i, j), &record)| {
+        let scale = MyFp48::checked_exp2(magnitude - term_forecast(i, j, width, height)).ok_or(YamakagashiError::CoefficientOverflow)?;
+        Ok(MyFp48::from_record_bytes(record) * scale)
+    }).collect()
+}
+
+#[test]
+#[allow(clippy::iter_skip_zero)]
+fn patch_compression_test() {
+    // 16x12 plane, a bilinear ramp fits one patch of degree 2
+    let plane: Vec<u8> = (0..16 * 12).map(|i| { let (x, y) = (i % 16, i / 16); (40 + 3 * x + 5 * y + x * y / 4) as u8 }).collect();
+    let page: Page<u8> = plane.iter().skip(0).step_by(1).take(plane.len());
+
+    let (degree, coeffs) = patch_compression(&page, 16, (0, 0, 16, 12), 99).unwrap().unwrap();
+    assert!(degree <= 2, "degree {degree}");
+    let terms = patch_terms(degree, 16, 12);
+    let decoded = evaluate_patch(16, 12, 1, &terms, &record_values::<u8>(&terms, &coeffs, 16, 12).unwrap());
+    let max_difference = decoded.iter().zip(&plane).map(|(&value, &sample)| (u8::from_my_fp48(value).unwrap() as i32 - sample as i32).abs()).max().unwrap();
+    assert!(max_difference <= 1, "max difference {max_difference}");
+
+    // a hard edge fits no patch
+    let plane: Vec<u8> = (0..16 * 12).map(|i| if i % 16 < 7 { 0 } else { 255 }).collect();
+    let page: Page<u8> = plane.iter().skip(0).step_by(1).take(plane.len());
+    assert_eq!(patch_compression(&page, 16, (0, 0, 16, 12), 99).unwrap(), None);
+}
@@ -1,29 +1,34 @@
-/// unit_compress use solve algorithm for Hankel-system
-/// 
-/// na = b -> a is nothing (when n isn't sq mtx)
-/// 
-/// n^tna = n^tb
-/// la = c  (n^tn def= l, n^tb def= c)
-/// a = l^-1c
-/// 
-/// b' = na
-/// 
-/// |b - b'|^2
-/// = |b|^2 + |b'|^2 - 2*b.dot(b')
-/// = b.dot(b) + a^tn^tna - 2*b^tna
-/// = b.dot(b) + c^t.dot(a) - 2*c^t.dot(a)
-/// = b.dot(b) - c^t.dot(a)
-/// 
-/// R^2 = 1 - sse/ssd
-/// R^2 = 1 - |b-b'|^2/|b-b_m|^2 , b_m is mean of b
-
+//! unit_compress use solve algorithm for Hankel-system
+//!
+//! na = b -> a is nothing (when n isn't sq mtx)
+//!
+//! n^tna = n^tb
+//! la = c  (n^tn def= l, n^tb def= c)
+//! a = l^-1c
+//!
+//! b' = na
+//!
+//! |b - b'|^2
+//! = |b|^2 + |b'|^2 - 2*b.dot(b')
+//! = b.dot(b) + a^tn^tna - 2*b^tna
+//! = b.dot(b) + c^t.dot(a) - 2*c^t.dot(a)
+//! = b.dot(b) - c^t.dot(a)
+//!
+//! R^2 = 1 - sse/ssd
+//! R^2 = 1 - |b-b'|^2/|b-b_m|^2 , b_m is mean of b
 use crate::my_float::MyFp48;
 use crate::my_vector::{VecTool, HadamardProduct};
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+use super::Page;
 // use crate::my_vector::DisplayVec;
 
 // unit transform and compression
 
-pub fn unit_compression(b: std::iter::Take<std::iter::Skip<std::iter::Take<std::iter::StepBy<std::iter::Skip<std::slice::Iter<'_, u8>>>>>>, quality: i32) -> Vec<u16> {
+// samples of one unit picked from a page
+pub type UnitSamples<'a, S> = std::iter::Take<std::iter::Skip<Page<'a, S>>>;
+
+pub fn unit_compression<S: Sample>(b: UnitSamples<S>, quality: i32) -> Result<Vec<u16>> {
     let n: usize = b.len();
     let x:Vec<MyFp48> = (0..n).map(|i| MyFp48::new((-(n as i32)+1 + 2*i as i32) as f32 / 2.0)).collect(); // x == [(-n+1)/2, (-n+3)/2..(n-3)/2,(n-1)/2]
     let b_sq_norm = b.sq_norm();
@@ -52,14 +57,14 @@ pub fn unit_compression(b: std::iter::Take<std::iter::Skip<std::iter::Take<std::
                 let error_f = l[m .. 2*m].dot(f.iter().take(m));
                 let error_g = l[m+1 .. 2*m+1].dot(g.iter().take(m));
                 let diff = error_f-error_g;
-                assert!(!diff.is_zero(), "diff: {diff}");
+                if diff.is_zero() { return Err(YamakagashiError::SingularFit { unit_size: n }); }
                 f.iter_mut().take(m+1).zip([MyFp48::ZERO].iter().chain(g.iter().take(m)))
                     .for_each(|(_f, &_g)| *_f = (*_f-_g)/diff );
             } else {
                 let error_f = l[m+1 .. 2*m+2].dot(f.iter().take(m+1));
                 let error_g = l[m+1 .. 2*m+1].dot(g.iter().take(m));
                 let diff = error_g-error_f;
-                assert!(!diff.is_zero(), "diff: {diff}");
+                if diff.is_zero() { return Err(YamakagashiError::SingularFit { unit_size: n }); }
                 g.iter_mut().take(m+1).zip(f.iter().take(m+1))
                     .for_each(|(_g, &_f)| *_g = (*_g-_f)/diff );
             }
@@ -67,10 +72,10 @@ pub fn unit_compression(b: std::iter::Take<std::iter::Skip<std::iter::Take<std::
 
         // make new a
         if i % 2 == 0 {
-            let error_a = l[m .. 2*m].dot(a.iter().skip(0).step_by(2).take(m));
+            let error_a = l[m .. 2*m].dot(a.iter().step_by(2).take(m));
             let diff = c[i] - error_a;
-            a.iter_mut().skip(0).step_by(2).take(m+1).zip(f.iter().take(m+1)).for_each(|(_a, &_f)| *_a += diff*_f );
-            ac_even = a.iter().skip(0).step_by(2).take(m+1).dot(c.iter().skip(0).step_by(2).take(m+1));
+            a.iter_mut().step_by(2).take(m+1).zip(f.iter().take(m+1)).for_each(|(_a, &_f)| *_a += diff*_f );
+            ac_even = a.iter().step_by(2).take(m+1).dot(c.iter().step_by(2).take(m+1));
         } else {
             let error_a = l[m+1 .. 2*m+1].dot(a.iter().skip(1).step_by(2).take(m));
             let diff = c[i] - error_a;
@@ -84,88 +89,86 @@ pub fn unit_compression(b: std::iter::Take<std::iter::Skip<std::iter::Take<std::
 
         // quality check
         if b_sq_norm * MyFp48::new(quality as f32 / 100.0) < ac_even + ac_odd {
-            return round_to_record_u16(a);
+            return round_to_record_u16::<S>(a);
         }
     }
 
     // println!("quality isn't satisfy (T_T) final quality is: {:.3}", MyFp48::ONE - sse/ssd);
-    return round_to_record_u16(a);
+    round_to_record_u16::<S>(a)
 }
 
-fn round_to_record_u16(vec:Vec<MyFp48>) -> Vec<u16> {
+fn round_to_record_u16<S: Sample>(vec:Vec<MyFp48>) -> Result<Vec<u16>> {
 
     let size = vec.len();
-    let mut out_vec: Vec<u16> = Vec::with_capacity(size); // coeff*(size/2)^i ~ 2^7 -> coeff ~ 2^-n? // coeff ~ 2^(7-i*(log2(size)-1))
+    let mut out_vec: Vec<u16> = Vec::with_capacity(size + 1); // coeff*(size/2)^i ~ 2^7 -> coeff ~ 2^-n? // coeff ~ 2^(7-i*(log2(size)-1))
+    // 7 for 8-bit, a sample is about 2^(BITS-1), a float unit puts its own ahead of the records
+    let magnitude = match S::MAGNITUDE {
+        Some(magnitude) => magnitude,
+        None => {
+            let magnitude = magnitude_of(&vec);
+            out_vec.push(magnitude as i16 as u16);
+            magnitude
+        },
+    };
 
     // when is vec constant functions-coeffs
     let is_constant: bool = vec.iter().skip(1).fold(true, |acc, ele| acc & ele.is_zero() );
     if is_constant {
 
-        let round_coeff = match vec[0].round_u8() {
-            Ok(value) => {
-                MyFp48::new(value as f32)
-            },
-            Err(_) => panic!("can't round u8, because too big"),
-        };
+        let round_coeff = MyFp48::new(S::from_my_fp48(vec[0])?.into());
 
-        out_vec = vec![0; size];
-        out_vec[0] = match round_coeff.to_record_bytes_with_forecast(-7) {
+        out_vec.push(match round_coeff.to_record_bytes_with_forecast(-magnitude) {
             Ok(record_f16) => record_f16,
             Err("can't express f16, because of this MyFp48 abs is too small") => 0x0000,
-            Err("can't express f16, because of this MyFp48 abs is too big") => {
-                println!("can't express f16, because of this MyFp48 abs is too big");
-
-                println!("returns record f16 max instead");
-                // let record_u16_max: u16 = 
-                if round_coeff.sign() == 1 { 0x7FFF }
-                else { 0xFFFF }
-            },
-            _ => panic!("may can't see you"),
-        };
-
-        return out_vec;
+            // record f16 max instead
+            Err("can't express f16, because of this MyFp48 abs is too big") => if round_coeff.sign() == 1 { 0x7FFF } else { 0xFFFF },
+            Err(why) => return Err(YamakagashiError::UnrecordableCoefficient(why)),
+        });
+        out_vec.resize(out_vec.len() + size - 1, 0);
+
+        return Ok(out_vec);
     }
     
-    vec.iter().enumerate().for_each(|(i, coeff)| {
+    for (i, coeff) in vec.iter().enumerate() {
 
         let log_size = (size as f64).log2();
-        let forecast_coeff = (i as f64 * (log_size - 1.0) - 7.0).trunc() as i32; // coeff*(size/2)^i ~ 2^7 -> coeff ~ 2^-n? // coeff ~ 2^(7-i*(log2(size)-1)) // forecast max = 2^x(x-1)-7 // x = 8, max = 1785 < 2^11 // x = 16, max = 983033 < 2^20 // my_float32 s1e20f11
+        let forecast_coeff = (i as f64 * (log_size - 1.0) - magnitude as f64).trunc() as i32; // coeff*(size/2)^i ~ 2^7 -> coeff ~ 2^-n? // coeff ~ 2^(7-i*(log2(size)-1)) // forecast max = 2^x(x-1)-7 // x = 8, max = 1785 < 2^11 // x = 16, max = 983033 < 2^20 // my_float32 s1e20f11
 
         // let adjusted_coeff = *coeff*MyFp48::exp2(forecast_coeff);
 
         match coeff.to_record_bytes_with_forecast(forecast_coeff) {
             Ok(record_f16) => out_vec.push(record_f16),
             Err("can't express f16, because of this MyFp48 abs is too small") => out_vec.push(0x0000),
-            Err("can't express f16, because of this MyFp48 abs is too big") => {
-                println!("can't express f16, because of this MyFp48 abs is too big");
-                if (coeff.exponent() == -172) && (forecast_coeff == 191) { 
-                    println!("{:?}", vec.iter().map(|&ele| ele.exponent() ).collect::<Vec<_>>());
-                    panic!("-172")
-                }
-                println!("returns record f16 max instead");
-                let record_u16_max: u16 = 
-                if coeff.sign() == 1 { 0x7FFF }
-                else { 0xFFFF };
-                out_vec.push(record_u16_max);
-            },
-            _ => panic!("may can't see you"),
+            // record f16 max instead
+            Err("can't express f16, because of this MyFp48 abs is too big") => out_vec.push(if coeff.sign() == 1 { 0x7FFF } else { 0xFFFF }),
+            Err(why) => return Err(YamakagashiError::UnrecordableCoefficient(why)),
         }
-    });
+    }
     
-    out_vec
+    Ok(out_vec)
+}
+
+// magnitude which puts the biggest record of the unit at exponent 0
+fn magnitude_of(vec: &[MyFp48]) -> i32 {
+
+    let log_size = (vec.len() as f64).log2();
+    vec.iter().enumerate().filter(|(_, coeff)| !coeff.is_zero())
+        .map(|(i, coeff)| coeff.exponent() + (i as f64 * (log_size - 1.0)).trunc() as i32)
+        .max().unwrap_or(0).clamp(i16::MIN as i32, i16::MAX as i32)
 }
 
 #[test]
+#[allow(clippy::iter_skip_zero)]
 fn unit_compression_test() {
     
     let test_case = 
     [30, 32, 35, 32, 33, 32, 34, 35, 31, 28, 32, 29, 28, 33, 33, 30, 33, 34, 29, 31, 34, 29, 28, 29, 30, 32, 30, 28, 30, 28, 29, 32, 28, 30, 34, 30, 25, 30, 29, 28, 33, 29, 25, 32, 31, 28, 33, 30, 29, 28, 25, 28, 28, 28, 29, 34, 27, 26, 33, 30, 27, 32, 29, 27, 29, 27, 27, 31, 27, 25, 30, 31, 31, 31, 30, 29, 27, 26, 26, 26, 32, 30, 27, 29, 25, 23, 28, 31, 27, 24, 26, 23, 26, 30, 28, 24, 28, 28, 28, 28, 28, 27, 26, 27, 26, 25, 25, 26, 25, 28, 27, 25, 26, 29, 26, 25, 30, 26, 22, 25, 27, 27, 23, 23, 26, 24, 27, 25, 23, 26, 30, 26, 23, 24, 27, 26, 23, 28, 29, 26, 28, 27, 25, 24, 24, 28, 24, 24, 28, 22, 22, 26, 30, 27, 22, 24, 28, 27, 28, 25, 23, 25, 27, 27, 24, 21, 24, 26, 23, 23, 24, 22, 22, 23, 22, 23, 21, 24, 25, 21, 20, 23, 25, 23, 24, 21, 22, 23, 22, 22, 23, 23, 23, 23, 21, 22, 22, 23, 23, 22, 22, 23, 23, 22, 22, 22, 21, 23, 23, 25, 23, 21, 24, 24, 23, 25, 23, 22, 25, 25, 23, 24, 23, 22, 21, 23, 23, 22, 22, 24, 27, 23]
     ;
     let test_len = test_case.len();
-    let test_iter: std::iter::Take<std::iter::Skip<std::iter::Take<std::iter::StepBy<std::iter::Skip<std::slice::Iter<'_, u8>>>>>> 
+    let test_iter: UnitSamples<u8>
      = test_case.iter().skip(0).step_by(1).take(test_len).skip(0).take(test_len);
 
-    let comp = unit_compression(test_iter, 85);
+    let comp = unit_compression(test_iter, 85).unwrap();
     println!("{:?}", comp);
     // let ans = 
     // [44585, 48348, 14250, 14013, 47434, 49898, 12976, 17532, 13318, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
@@ -180,6 +183,7 @@ fn unit_compression_test() {
 }
 
 #[test]
+#[allow(clippy::excessive_precision)]
 fn cmp() {
 
     let ele = MyFp48 {base:2.11588983E-37, extra_exponent:32768};
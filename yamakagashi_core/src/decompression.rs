@@ -1,107 +1,430 @@
-/// decompress will output Vec<u8> bitmap
-/// looks like lighter than compression process, but actually decompress process is lighter than compression that
-/// 
-
-
+//! decompress will output `Vec<u8>` bitmap
+//! looks like lighter than compression process, but actually decompress process is lighter than compression that
 use crate::my_float::MyFp48;
+use crate::compression::CompressedPage;
+use crate::compression::patch_compression::{evaluate_patch_window, patch_terms, quadrants, record_values, root_rects, PatchNode, Rect, MAX_PATCH_DEGREE, PATCH_NODE, ROWS_NODE, SPLIT_NODE};
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+use crate::color_transform::{inverse_transform, ColorTransform};
+use crate::subsampling::{upsample_rows, upsampled_from, ChromaSubsampling};
+use crate::coding_options::CodingOptions;
+use crate::scan::{transpose, ScanDirection};
+use crate::tiling::{check_region, TileRect};
+use crate::parallel::{map_indices, map_slice};
+use crate::inter_row::{delta_against_above, COEFF_DELTA, SAME_UNIT_SIZES};
 use super::my_vector::HadamardProduct;
 use std::collections::LinkedList;
+use std::ops::Range;
 
 // unit decompress and detransform, rebuild bitmap
 
-pub fn image_decompression(yamakagashi_bytes: &Vec<u8>, number_of_colors: u8, size:(u32, u32)) -> Vec<u8> {
-    
-    let yamakagashi = organize(yamakagashi_bytes, number_of_colors, size);
+// options.lossless_alpha: the last plane follows the coded planes as raw bytes
+// options.color_transform: luma and chroma planes are turned back into B, G and R at last
+// options.subsampling: chroma planes are brought back to full resolution
+// options.shared_units: unit sizes of planes of full resolution come once ahead of all planes
+// options.inter_row: row modes of all rows come first, a row may take unit sizes and coeffs from the row above
+// options.patches: planes are quad-trees of 2-D patches and rows of units
+// options.scan: columns decodes the transposed image and turns it back
+// options.trailing_zeros: units store a coeff for every sample instead of a count of their coeffs
+// options.varint_sizes: unit sizes and counts are varints instead of u16
+pub fn image_decompression<S: Sample>(yamakagashi_bytes: &[u8], number_of_colors: u8, size:(u32, u32), options: &CodingOptions) -> Result<Vec<S>> {
+    region_decompression(yamakagashi_bytes, number_of_colors, size, options, (0, 0, size.0, size.1))
+}
+
+// pixels of region only, the whole stream is read but only the units in region are evaluated
+pub fn region_decompression<S: Sample>(yamakagashi_bytes: &[u8], number_of_colors: u8, size:(u32, u32), options: &CodingOptions, region: TileRect) -> Result<Vec<S>> {
+
+    options.check(number_of_colors)?;
+    check_region(region, size)?;
+    if options.scan == ScanDirection::Columns {
+        let (rows, coded_size) = (CodingOptions { scan: ScanDirection::Rows, ..*options }, options.scan.coded_size(size));
+        let (x, y, width, height) = region;
+        return Ok(transpose(&region_decompression::<S>(yamakagashi_bytes, number_of_colors, coded_size, &rows, (y, x, height, width))?, number_of_colors, (height, width)));
+    }
+    let coded_colors = options.coded_colors(number_of_colors);
+
+    // every coded subpixel has at least one u16 coeff and every raw one a sample, without trailing zeros
+    // every row of a plane has at least a u16 count for each u16::MAX samples, or a varint unit size and count,
+    // so a header asking for more than the stream can hold is refused before allocating
+    let pixels = (size.0 as usize).checked_mul(size.1 as usize)
+        .filter(|pixels| pixels.checked_mul(number_of_colors as usize).is_some())
+        .ok_or_else(|| YamakagashiError::UnsupportedHeader(format!("image size {}x{}x{} is too big", size.0, size.1, number_of_colors)))?;
+    let raw_samples = if options.lossless_alpha { pixels } else { 0 };
+    let raw_size = raw_samples.saturating_mul(S::BYTES);
+    let plane_sizes: Vec<(u32, u32)> = (0..coded_colors as usize).map(|which_color| options.subsampling.plane_size(size, which_color)).collect();
+    let coded_samples: usize = plane_sizes.iter().map(|&(width, height)| width as usize * height as usize).sum();
+    // a patch covers many samples, but every root square has its tag at least
+    let coded_size = if options.patches {
+        plane_sizes.iter().map(|&plane_size| root_rects(plane_size).len()).sum::<usize>() * 2
+    } else if options.trailing_zeros {
+        coded_samples.saturating_mul(2)
+    } else if options.varint_sizes {
+        plane_sizes.iter().map(|&(_, height)| height as usize).sum::<usize>() * 2
+    } else {
+        plane_sizes.iter().map(|&(width, height)| width.div_ceil(u16::MAX as u32) as usize * height as usize).sum::<usize>() * 2
+    };
+    let needed = coded_size.saturating_add(raw_size);
+    if yamakagashi_bytes.len() < needed {
+        return Err(YamakagashiError::TruncatedStream { needed, available: yamakagashi_bytes.len() });
+    }
+    let (coded_bytes, raw_bytes) = yamakagashi_bytes.split_at(yamakagashi_bytes.len() - raw_size);
+
+    let shared: Vec<bool> = plane_sizes.iter().map(|&plane_size| options.shared_units && plane_size == size).collect();
+    let mut image: Vec<S> = match options.color_transform {
+        ColorTransform::None => planes_decompression(coded_bytes, number_of_colors, size, &plane_sizes, &shared, region, options)?,
+        _ => inverse_transform::<S>(&planes_decompression(coded_bytes, number_of_colors, size, &plane_sizes, &shared, region, options)?, number_of_colors, options.color_transform),
+    };
+
+    if options.lossless_alpha {
+        let (x, y, width, _) = region;
+        let raw_samples: Vec<&[u8]> = raw_bytes.chunks_exact(S::BYTES).collect();
+        image.iter_mut().skip(coded_colors as usize).step_by(number_of_colors as usize).enumerate().for_each(|(i, a)| {
+            let (column, row) = (x as usize + i % width as usize, y as usize + i / width as usize);
+            *a = S::read_be(raw_samples[row * size.0 as usize + column]);
+        });
+    }
+
+    Ok(image)
+}
+
+// the most bytes the stream of an image can unpack to, a backend unpacking more has a broken stream
+// a unit of one sample is at most a u16 size, count, magnitude and coeff, row modes and shared sizes add a u16 a row,
+// a patch of one sample is no bigger with its tag and the splits above it, a raw sample is at most 4 bytes
+pub fn max_stream_size(number_of_colors: u8, size: (u32, u32), options: &CodingOptions) -> usize {
+    (0..number_of_colors as usize).map(|which_color| options.subsampling.plane_size(size, which_color))
+        .map(|(width, height)| (width as usize).saturating_mul(height as usize).saturating_mul(16))
+        .fold(0, usize::saturating_add)
+}
+
+// the planes of plane_sizes in region from units, the others are left default
+fn planes_decompression<S: Sample>(coded_bytes: &[u8], number_of_colors: u8, size:(u32, u32), plane_sizes: &[(u32, u32)], shared: &[bool], region: TileRect, options: &CodingOptions) -> Result<Vec<S>> {
+
+    let subsampling: ChromaSubsampling = options.subsampling;
+    // subsampled units are evaluated at full width
+    let scales: Vec<usize> = plane_sizes.iter().map(|plane_size| if plane_size.0 == size.0 { 1 } else { subsampling.factor().0 as usize }).collect();
+    let (x, y, width, height) = region;
+    // columns of the region in rows evaluated at full width
+    let columns = x as usize..(x + width) as usize;
+    // rows of each plane the region needs, subsampled rows are interpolated from the rows around
+    let plane_rows: Vec<Range<usize>> = plane_sizes.iter().map(|&plane_size| {
+        if plane_size.1 == size.1 || height == 0 { y as usize..(y + height) as usize } else { upsampled_from(y..y + height, plane_size.1, subsampling) }
+    }).collect();
+
+    let pages_rows: Vec<Vec<Vec<S>>> = if options.patches {
+        let patch_pages = organize_patches::<S>(coded_bytes, plane_sizes, options.trailing_zeros, options.varint_sizes)?;
+        map_indices(patch_pages.len(), |i| patch_page_rows(&patch_pages[i], plane_sizes[i], scales[i], columns.clone(), plane_rows[i].clone())).into_iter().collect::<Result<_>>()?
+    } else {
+        let compressed_pages = organize::<S>(coded_bytes, plane_sizes, shared, options.inter_row, options.trailing_zeros, options.varint_sizes)?;
+        map_indices(compressed_pages.len(), |i| {
+            map_slice(&compressed_pages[i][plane_rows[i].clone()], |page_row| units_row(page_row, scales[i], columns.clone())).into_iter().collect::<Result<_>>()
+        }).into_iter().collect::<Result<_>>()?
+    };
 
-    let mut image: Vec<u8> = vec![0; (size.0*size.1*number_of_colors as u32) as usize];
+    let mut image: Vec<S> = vec![S::default(); width as usize * height as usize * number_of_colors as usize];
 
-    for (select_color, compressed_page) in yamakagashi.iter().enumerate() {
+    for (select_color, ((rows, &plane_size), plane_rows)) in pages_rows.into_iter().zip(plane_sizes).zip(plane_rows).enumerate() {
 
-        // let mut color_page = image.iter().skip(color).step_by(number_of_colors).take(size.0*size.1);
-        for (i, page_row) in compressed_page.iter().enumerate() {
+        let plane = if plane_size.1 == size.1 { rows.concat() } else { upsample_rows(&rows, plane_rows.start, plane_size.1, y..y + height, subsampling) };
+        image.iter_mut().skip(select_color).step_by(number_of_colors as usize) // select color
+            .zip(plane).for_each(|(a, b)| *a = b);
+    }
+
+    Ok(image)
+}
 
-            let mut skip = 0;
-            for (unit_size, unit_coeffs) in page_row {
-                let temp_unit = unit_decompression(*unit_size as usize, unit_coeffs);
-                image.iter_mut().skip(select_color).step_by(number_of_colors as usize) // select color
-                .skip(i*size.0 as usize) // select row
-                .skip(skip).take(*unit_size as usize) // select unit
-                .zip(temp_unit.iter()).for_each(|(a, b)| *a = *b);
-            skip += *unit_size as usize;
+// samples in columns of a row of units, units out of columns are skipped
+fn units_row<S: Sample>(page_row: &LinkedList<(u32, Vec<u16>)>, scale: usize, columns: Range<usize>) -> Result<Vec<S>> {
+
+    let mut row = Vec::with_capacity(columns.len());
+    let mut start = 0;
+    for (unit_size, unit_coeffs) in page_row {
+        let end = start + *unit_size as usize * scale;
+        let points = start.max(columns.start)..end.min(columns.end);
+        if !points.is_empty() {
+            row.extend(unit_decompression::<S>(*unit_size as usize, unit_coeffs, scale, points.start - start..points.end - start)?);
         }
-            assert_eq!(skip, size.0 as usize);
+        start = end;
+    }
+
+    Ok(row)
+}
+
+// rows of a page of quad-trees in columns, nodes out of them are skipped
+fn patch_page_rows<S: Sample>(patch_page: &[PatchNode], size: (u32, u32), scale: usize, columns: Range<usize>, page_rows: Range<usize>) -> Result<Vec<Vec<S>>> {
+
+    fn render<S: Sample>(node: &PatchNode, rect: Rect, scale: usize, columns: &Range<usize>, page_rows: &Range<usize>, rows: &mut [Vec<S>]) -> Result<()> {
+        let (x, y, width, height) = rect;
+        // columns of the rows, which are scale times as wide as the plane
+        let (left, right) = (x as usize * scale, (x + width) as usize * scale);
+        // the part of the node in the window
+        let (node_columns, node_rows) = (left.max(columns.start)..right.min(columns.end), (y as usize).max(page_rows.start)..(y + height).min(page_rows.end as u32) as usize);
+        if node_columns.is_empty() || node_rows.is_empty() { return Ok(()); }
+        let window_columns = node_columns.start - columns.start..node_columns.end - columns.start;
+        let window_rows = &mut rows[node_rows.start - page_rows.start..node_rows.end - page_rows.start];
+        match node {
+            PatchNode::Split(nodes) => {
+                for (node, quadrant) in nodes.iter().zip(quadrants(rect)) {
+                    render(node, quadrant, scale, columns, page_rows, rows)?;
+                }
+            },
+            PatchNode::Rows(units_rows) => {
+                for (row, page_row) in window_rows.iter_mut().zip(units_rows.iter().skip(node_rows.start - y as usize)) {
+                    row[window_columns.clone()].copy_from_slice(&units_row::<S>(page_row, scale, node_columns.start - left..node_columns.end - left)?);
+                }
+            },
+            PatchNode::Patch { degree, coeffs } => {
+                let terms = patch_terms(*degree, width, height);
+                let values = evaluate_patch_window(width, height, scale, &terms, &record_values::<S>(&terms, coeffs, width, height)?,
+                    node_columns.start - left..node_columns.end - left, node_rows.start - y as usize..node_rows.end - y as usize);
+                for (row, patch_row) in window_rows.iter_mut().zip(values.chunks(node_columns.len())) {
+                    for (sample, &value) in row[window_columns.clone()].iter_mut().zip(patch_row) {
+                        *sample = S::from_my_fp48(value)?;
+                    }
+                }
+            },
         }
+        Ok(())
     }
 
-    image
+    let mut rows = vec![vec![S::default(); columns.len()]; page_rows.len()];
+    for (node, rect) in patch_page.iter().zip(root_rects(size)) {
+        render(node, rect, scale, &columns, &page_rows, &mut rows)?;
+    }
+
+    Ok(rows)
 }
 
-fn unit_decompression(unit_size:usize, unit_coeffs:&Vec<u16>) -> Vec<u8> {
+// scale: the unit is evaluated at scale points per sample, each in the middle of its part
+// points: the points evaluated, out of unit_size*scale
+fn unit_decompression<S: Sample>(unit_size:usize, unit_coeffs:&[u16], scale:usize, points:Range<usize>) -> Result<Vec<S>> {
 
-    assert_eq!(unit_size, unit_coeffs.len());
-    let mut temp_unit: Vec<MyFp48> = vec![MyFp48::ZERO; unit_size];
+    // a float unit starts with its magnitude
+    let (magnitude, unit_coeffs) = match S::MAGNITUDE {
+        Some(magnitude) => (magnitude, unit_coeffs),
+        None => (unit_coeffs[0] as i16 as i32, &unit_coeffs[1..]),
+    };
+    // coeffs after the stored ones are zeros
+    debug_assert!(unit_coeffs.len() <= unit_size);
+    let mut temp_unit: Vec<MyFp48> = vec![MyFp48::ZERO; points.len()];
 
-    let mut zero_run_point = unit_size;
+    let mut zero_run_point = unit_coeffs.len();
     for &coeff in unit_coeffs.iter().rev() {
         if coeff != 0u16 {break;}
         zero_run_point -= 1;
     }
     
-    let x:Vec<MyFp48> = (0..unit_size).map(|i| MyFp48::new((-(unit_size as i32)+1 + 2*i as i32) as f32 / 2.0)).collect(); // x == [(-n+1)/2, (-n+3)/2..(n-3)/2,(n-1)/2]
-    let mut power_x = vec![MyFp48::ONE; unit_size];
+    let x:Vec<MyFp48> = points.clone().map(|i| MyFp48::new(((2*i+1) as f32 / scale as f32 - unit_size as f32) / 2.0)).collect(); // x == [(-n+1)/2, (-n+3)/2..(n-3)/2,(n-1)/2] when scale is 1
+    let mut power_x = vec![MyFp48::ONE; points.len()];
     for (i, &coeff) in (0..zero_run_point).zip(unit_coeffs) {
         let log_size = (unit_size as f64).log2();
-        let forecast_coeff = (7.0 - i as f64 * (log_size - 1.0)).trunc() as i32;
-        let actuall_coeff = MyFp48::from_record_bytes(coeff) * MyFp48::exp2(forecast_coeff);
+        let forecast_coeff = (magnitude as f64 - i as f64 * (log_size - 1.0)).trunc() as i32;
+        // a long unit of a crafted stream can push the forecast out of the range of MyFp48
+        let actuall_coeff = MyFp48::from_record_bytes(coeff) * MyFp48::checked_exp2(forecast_coeff).ok_or(YamakagashiError::CoefficientOverflow)?;
 
         temp_unit.iter_mut().zip(power_x.iter()).for_each(|(a,b)| *a += *b*actuall_coeff);
         power_x.hadamard_product(&x);
     }
 
-    temp_unit.iter().map(|a| {
-        match a.round_u8() {
-            Ok(value) => value,
-            Err(_) => panic!("can't round u8, because too big"),
-        }
-    } ).collect()
+    temp_unit.iter().map(|&a| S::from_my_fp48(a)).collect()
 }
 
-fn organize(yamakagashi_bytes: &Vec<u8>, number_of_colors: u8, size: (u32, u32)) -> Vec<Vec<LinkedList<(u16, Vec<u16>)>>> {
+// read one big-endian u16 and move index forward
+fn read_u16(yamakagashi_bytes: &[u8], index: &mut usize) -> Result<u16> {
 
-    let mut yamakagashi: Vec<Vec<LinkedList<(u16, Vec<u16>)>>> = Vec::with_capacity(number_of_colors as usize);
+    let bytes = yamakagashi_bytes.get(*index..*index+2)
+        .ok_or(YamakagashiError::TruncatedStream { needed: *index+2, available: yamakagashi_bytes.len() })?;
+    *index += 2;
+
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// read one unit size or coeff count and move index forward
+// varint_sizes: 7 bits a byte from the top, the high bit tells another byte follows, otherwise a big-endian u16
+fn read_length(yamakagashi_bytes: &[u8], index: &mut usize, varint_sizes: bool) -> Result<u32> {
+
+    if !varint_sizes {
+        return read_u16(yamakagashi_bytes, index).map(u32::from);
+    }
+    let mut length = 0u32;
+    loop {
+        let byte = *yamakagashi_bytes.get(*index)
+            .ok_or(YamakagashiError::TruncatedStream { needed: *index+1, available: yamakagashi_bytes.len() })?;
+        *index += 1;
+        // an overlong length saturates and is refused by the caller as too big
+        length = length.checked_mul(0x80).map_or(u32::MAX, |length| length | (byte & 0x7F) as u32);
+        if byte & 0x80 == 0 { return Ok(length); }
+    }
+}
+
+// read one unit size, row_size is the sum of the unit sizes before it in the row
+fn read_unit_size(yamakagashi_bytes: &[u8], index: &mut usize, row: usize, row_size: u32, width: u32, varint_sizes: bool) -> Result<u32> {
+
+    let unit_size = read_length(yamakagashi_bytes, index, varint_sizes)?;
+    // a zero sized unit never finishes the row, and an overlong one runs over it
+    if unit_size == 0 || row_size.saturating_add(unit_size) > width {
+        return Err(YamakagashiError::UnitSizeMismatch { row, expected: width, actual: row_size.saturating_add(unit_size) });
+    }
+
+    Ok(unit_size)
+}
+
+fn read_coeffs(yamakagashi_bytes: &[u8], index: &mut usize, count: usize) -> Result<Vec<u16>> {
+    (0..count).map(|_| read_u16(yamakagashi_bytes, index)).collect()
+}
+
+// coeffs of a unit of limit samples or a patch of limit terms, with the header of a float one ahead
+// trailing_zeros: a coeff for every sample or term, otherwise a count and as many coeffs, the zeros after them are left out
+fn read_unit_coeffs(yamakagashi_bytes: &[u8], index: &mut usize, header_size: usize, limit: u32, trailing_zeros: bool, varint_sizes: bool) -> Result<Vec<u16>> {
+
+    let count = if trailing_zeros { limit } else { read_length(yamakagashi_bytes, index, varint_sizes)? };
+    if count > limit {
+        return Err(YamakagashiError::TooManyCoefficients { count, limit });
+    }
+
+    read_coeffs(yamakagashi_bytes, index, header_size + count as usize)
+}
+
+// read unit sizes of one row up to width
+fn read_row_unit_sizes(yamakagashi_bytes: &[u8], index: &mut usize, row: usize, width: u32, varint_sizes: bool) -> Result<Vec<u32>> {
+
+    let mut unit_sizes = Vec::new();
+    let mut row_size = 0;
+    while row_size < width {
+        let unit_size = read_unit_size(yamakagashi_bytes, index, row, row_size, width, varint_sizes)?;
+        unit_sizes.push(unit_size);
+        row_size += unit_size;
+    }
+
+    Ok(unit_sizes)
+}
+
+// the first row has no row above to take unit sizes from
+fn unit_sizes_above(above: Option<Vec<u32>>, row: usize, width: u32) -> Result<Vec<u32>> {
+    above.ok_or(YamakagashiError::UnitSizeMismatch { row, expected: width, actual: 0 })
+}
+
+// shared: the plane takes the unit sizes written once ahead of all planes instead of its own
+// inter_row: row modes of all rows come first, see inter_row
+fn organize<S: Sample>(yamakagashi_bytes: &[u8], plane_sizes: &[(u32, u32)], shared: &[bool], inter_row: bool, trailing_zeros: bool, varint_sizes: bool) -> Result<Vec<CompressedPage>> {
+
+    // magnitude of a float unit is one more u16
+    let header_size = S::MAGNITUDE.is_none() as usize;
+
+    let mut yamakagashi: Vec<CompressedPage> = Vec::with_capacity(plane_sizes.len());
 
     let mut index: usize = 0;
-    for _ in 0..number_of_colors {
-
-        let mut yamakagashi_row = Vec::with_capacity(size.1 as usize);
-        for _ in 0..size.1 {
-
-            let mut yamakagashi_units: LinkedList<(u16, Vec<u16>)> = LinkedList::new();
-            
-            let mut row_size = 0;
-            while row_size < size.0 {
-                let unit_size = u16::from_be_bytes(yamakagashi_bytes[index..index+2].try_into().unwrap());
-                index += 2;
-                let mut unit_coeffs = Vec::with_capacity(unit_size as usize);
-                for _ in 0..unit_size {
-                    unit_coeffs.push(u16::from_be_bytes(yamakagashi_bytes[index..index+2].try_into().unwrap()));
-                    index += 2;
-                }
+    let shared_size = plane_sizes.iter().zip(shared).find(|(_, &shared)| shared).map(|(&size, _)| size);
+    let rows = shared_size.map_or(0, |size| size.1 as usize) + plane_sizes.iter().map(|size| size.1 as usize).sum::<usize>();
+    let mut row_modes = if inter_row { read_coeffs(yamakagashi_bytes, &mut index, rows)? } else { vec![0; rows] }.into_iter();
+
+    let mut shared_unit_sizes: Vec<Vec<u32>> = Vec::new();
+    if let Some(size) = shared_size {
+        for row in 0..size.1 as usize {
+            let row_mode = row_modes.next().unwrap_or_default();
+            let unit_sizes = if row_mode & SAME_UNIT_SIZES != 0 {
+                unit_sizes_above(shared_unit_sizes.last().cloned(), row, size.0)?
+            } else {
+                read_row_unit_sizes(yamakagashi_bytes, &mut index, row, size.0, varint_sizes)?
+            };
+            shared_unit_sizes.push(unit_sizes);
+        }
+    }
+
+    for (&size, &shared) in plane_sizes.iter().zip(shared) {
+
+        let mut yamakagashi_row: CompressedPage = Vec::with_capacity(size.1 as usize);
+        for row in 0..size.1 as usize {
 
-                yamakagashi_units.push_back((unit_size, unit_coeffs));
+            let row_mode = row_modes.next().unwrap_or_default();
+            let above = yamakagashi_row.last();
 
-                row_size += unit_size as u32;
+            let mut yamakagashi_units: LinkedList<(u32, Vec<u16>)> = LinkedList::new();
+
+            match shared_unit_sizes.get(row).filter(|_| shared) {
+                Some(unit_sizes) => for &unit_size in unit_sizes {
+                    yamakagashi_units.push_back((unit_size, read_unit_coeffs(yamakagashi_bytes, &mut index, header_size, unit_size, trailing_zeros, varint_sizes)?));
+                },
+                None if row_mode & SAME_UNIT_SIZES != 0 => {
+                    let unit_sizes = unit_sizes_above(above.map(|above| above.iter().map(|unit| unit.0).collect()), row, size.0)?;
+                    for unit_size in unit_sizes {
+                        yamakagashi_units.push_back((unit_size, read_unit_coeffs(yamakagashi_bytes, &mut index, header_size, unit_size, trailing_zeros, varint_sizes)?));
+                    }
+                },
+                None => {
+                    let mut row_size = 0;
+                    while row_size < size.0 {
+                        let unit_size = read_unit_size(yamakagashi_bytes, &mut index, row, row_size, size.0, varint_sizes)?;
+                        yamakagashi_units.push_back((unit_size, read_unit_coeffs(yamakagashi_bytes, &mut index, header_size, unit_size, trailing_zeros, varint_sizes)?));
+                        row_size += unit_size;
+                    }
+                },
+            }
+
+            if row_mode & COEFF_DELTA != 0 {
+                if let Some(above) = above {
+                    yamakagashi_units = delta_against_above(&yamakagashi_units, above, header_size, false);
+                }
             }
-            assert_eq!(row_size, size.0, "This is incorrect file, row size and sum of unit size are not same!");
 
             yamakagashi_row.push(yamakagashi_units);
         }
         yamakagashi.push(yamakagashi_row);
     }
 
-    assert_eq!(index, yamakagashi_bytes.len(), "This is incorrect file, need data len and actually data len are not same!");
+    if index != yamakagashi_bytes.len() {
+        return Err(YamakagashiError::TrailingData { remaining: yamakagashi_bytes.len() - index });
+    }
+
+    Ok(yamakagashi)
+}
+
+// quad-trees of every plane, root squares row by row, see patch_compression
+fn organize_patches<S: Sample>(yamakagashi_bytes: &[u8], plane_sizes: &[(u32, u32)], trailing_zeros: bool, varint_sizes: bool) -> Result<Vec<Vec<PatchNode>>> {
+
+    // magnitude of a float patch or unit is one more u16
+    let header_size = S::MAGNITUDE.is_none() as usize;
+
+    fn read_node(yamakagashi_bytes: &[u8], index: &mut usize, rect: Rect, header_size: usize, trailing_zeros: bool, varint_sizes: bool) -> Result<PatchNode> {
+        let (_, y, width, height) = rect;
+        match read_u16(yamakagashi_bytes, index)? {
+            // a single sample has no quadrants smaller than itself
+            SPLIT_NODE if width > 1 || height > 1 => {
+                quadrants(rect).into_iter().map(|quadrant| read_node(yamakagashi_bytes, index, quadrant, header_size, trailing_zeros, varint_sizes)).collect::<Result<_>>().map(PatchNode::Split)
+            },
+            ROWS_NODE => {
+                let mut rows: CompressedPage = Vec::with_capacity(height as usize);
+                for row in y..y + height {
+                    let mut units = LinkedList::new();
+                    let mut row_size = 0;
+                    while row_size < width {
+                        let unit_size = read_unit_size(yamakagashi_bytes, index, row as usize, row_size, width, varint_sizes)?;
+                        units.push_back((unit_size, read_unit_coeffs(yamakagashi_bytes, index, header_size, unit_size, trailing_zeros, varint_sizes)?));
+                        row_size += unit_size;
+                    }
+                    rows.push(units);
+                }
+                Ok(PatchNode::Rows(rows))
+            },
+            tag if (PATCH_NODE..=PATCH_NODE + MAX_PATCH_DEGREE).contains(&tag) => {
+                let degree = tag - PATCH_NODE;
+                let coeffs = read_unit_coeffs(yamakagashi_bytes, index, header_size, patch_terms(degree, width, height).len() as u32, trailing_zeros, varint_sizes)?;
+                Ok(PatchNode::Patch { degree, coeffs })
+            },
+            tag => Err(YamakagashiError::BadPatchNode { tag }),
+        }
+    }
+
+    let mut index: usize = 0;
+    let mut patch_pages = Vec::with_capacity(plane_sizes.len());
+    for &size in plane_sizes {
+        patch_pages.push(root_rects(size).into_iter().map(|rect| read_node(yamakagashi_bytes, &mut index, rect, header_size, trailing_zeros, varint_sizes)).collect::<Result<Vec<PatchNode>>>()?);
+    }
 
-    yamakagashi
+    if index != yamakagashi_bytes.len() {
+        return Err(YamakagashiError::TrailingData { remaining: yamakagashi_bytes.len() - index });
+    }
+
+    Ok(patch_pages)
 }
 
 #[test]
@@ -116,12 +439,270 @@ fn unit_decompression_test() {
     [14657, 47264, 13348, 16187, 45601, 48731, 48971, 52474, 16292, 20565, 48862, 53498, 17906, 54319, 18028, 21274, 51305, 22056, 52272, 54454, 52042, 20597, 19974, 22140, 20567, 57336, 20745, 56115, 53818, 25669, 54984, 57537, 20097, 59482, 23254, 26305, 53804, 26778, 56007, 59264, 19444, 59718, 23259, 25380, 22631, 26594, 55559, 26637, 56189, 59976, 22720, 58977, 22898, 26836, 54479, 57588, 53945, 24704, 55095, 56961, 54940, 58799, 53778, 24758, 53384, 58057, 22190, 56965, 22262, 26034, 20495, 24147, 50428, 24151, 54325, 54561, 54255, 58024, 53219, 55816, 52596, 57005, 20082, 23640, 19909, 21671, 19661, 23144, 19070, 22735, 18737, 22075, 17576, 21469, 16405, 18798, 15970, 19688, 48138, 50138, 47624, 51228, 47422, 50858, 46679, 50758, 11863, 15336, 11355, 15894, 9369, 45880, 9260, 12082, 6419, 11371, 38951, 43776, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
     ;
     let unit_size = coeffs.len();
-    let value = unit_decompression(unit_size, &coeffs);
+    let value = unit_decompression::<u8>(unit_size, &coeffs, 1, 0..unit_size).unwrap();
 
-    let difference = value.iter().zip(ans.iter()).map(|(&_v, &_a)| (_v as i32 - _a)).collect::<Vec<_>>();
+    let difference = value.iter().zip(ans.iter()).map(|(&_v, &_a)| _v as i32 - _a).collect::<Vec<_>>();
     let difference_sum: i32 = difference.iter().map(|_d| _d.abs() ).sum();
     
     println!("{:?}", difference);
     println!("{}", difference_sum);
 
-}
\ No newline at end of file
+}
+#[test]
+fn broken_stream_test() {
+    // 2x1 image, one plane, one unit of size 2 with every coeff stored
+    let stream: Vec<u8> = vec![0, 2, 0x40, 0x00, 0, 0];
+    let legacy = CodingOptions { trailing_zeros: true, ..Default::default() };
+
+    assert!(image_decompression::<u8>(&stream, 1, (2, 1), &legacy).is_ok());
+    assert!(matches!(image_decompression::<u8>(&stream[..5], 1, (2, 1), &legacy), Err(YamakagashiError::TruncatedStream { .. })));
+    assert!(matches!(image_decompression::<u8>(&[stream.clone(), vec![0, 0]].concat(), 1, (2, 1), &legacy), Err(YamakagashiError::TrailingData { remaining: 2 })));
+    assert!(matches!(image_decompression::<u8>(&[0, 3, 0, 0, 0, 0, 0, 0], 1, (2, 1), &legacy), Err(YamakagashiError::UnitSizeMismatch { row: 0, expected: 2, actual: 3 })));
+    assert!(matches!(image_decompression::<u8>(&[0, 0, 0, 0, 0, 0], 1, (2, 1), &legacy), Err(YamakagashiError::UnitSizeMismatch { .. })));
+    assert!(matches!(image_decompression::<u8>(&stream, 1, (u32::MAX, u32::MAX), &legacy), Err(YamakagashiError::TruncatedStream { .. }) | Err(YamakagashiError::UnsupportedHeader(_))));
+
+    // the same unit with a count of one coeff, the zero after it is left out
+    let stream: Vec<u8> = vec![0, 2, 0, 1, 0x40, 0x00];
+    assert_eq!(image_decompression::<u8>(&stream, 1, (2, 1), &CodingOptions::default()).unwrap(), image_decompression::<u8>(&[0, 2, 0x40, 0x00, 0, 0], 1, (2, 1), &legacy).unwrap());
+    assert!(matches!(image_decompression::<u8>(&stream[..5], 1, (2, 1), &CodingOptions::default()), Err(YamakagashiError::TruncatedStream { .. })));
+    assert!(matches!(image_decompression::<u8>(&[0, 2, 0, 3, 0x40, 0x00, 0, 0, 0, 0], 1, (2, 1), &CodingOptions::default()), Err(YamakagashiError::TooManyCoefficients { count: 3, limit: 2 })));
+}
+#[test]
+fn varint_sizes_test() {
+    // a flat row longer than u16::MAX samples is one unit with varints, two with u16 sizes
+    let image: Vec<u8> = vec![100; 70000];
+    let varint = CodingOptions { varint_sizes: true, ..Default::default() };
+    let stream = crate::compression::image_compression(&image, 1, (70000, 1), 50, &CodingOptions::default()).unwrap();
+    let varint_stream = crate::compression::image_compression(&image, 1, (70000, 1), 50, &varint).unwrap();
+
+    assert_eq!(varint_stream[..3], [0x84, 0xA2, 0x70]);
+    assert!(varint_stream.len() < stream.len());
+    assert_eq!(image_decompression::<u8>(&varint_stream, 1, (70000, 1), &varint).unwrap(), image_decompression::<u8>(&stream, 1, (70000, 1), &CodingOptions::default()).unwrap());
+
+    // 2x1 unit of size 2 with a count of one coeff, a byte each
+    let stream: Vec<u8> = vec![2, 1, 0x40, 0x00];
+    assert_eq!(image_decompression::<u8>(&stream, 1, (2, 1), &varint).unwrap(), image_decompression::<u8>(&[0, 2, 0, 1, 0x40, 0x00], 1, (2, 1), &CodingOptions::default()).unwrap());
+    assert!(matches!(image_decompression::<u8>(&[0x82, 0x80], 1, (2, 1), &varint), Err(YamakagashiError::TruncatedStream { .. })));
+    assert!(matches!(image_decompression::<u8>(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F], 1, (2, 1), &varint), Err(YamakagashiError::UnitSizeMismatch { actual: u32::MAX, .. })));
+    assert!(matches!(image_decompression::<u8>(&[2, 3, 0x40, 0x00, 0, 0, 0, 0], 1, (2, 1), &varint), Err(YamakagashiError::TooManyCoefficients { count: 3, limit: 2 })));
+
+    // a unit of 2^20 samples with 450000 coeffs, the forecast of the last one is out of the range of MyFp48
+    let stream: Vec<u8> = [0xC0, 0x80, 0x00, 0x9B, 0xBB, 0x50].into_iter().chain((0..449999).flat_map(|_| [0, 0])).chain([0x3C, 0x00]).collect();
+    assert!(matches!(region_decompression::<u8>(&stream, 1, (1 << 20, 1), &varint, (0, 0, 1, 1)), Err(YamakagashiError::CoefficientOverflow)));
+}
+#[test]
+fn lossless_alpha_test() {
+    // 6x2 gray and alpha, alpha has sharp edges the units would smooth
+    let image: Vec<u8> = (0..12u8).flat_map(|i| [i * 20, if i % 3 == 0 { 0 } else { 255 }]).collect();
+
+    let stream = crate::compression::image_compression(&image, 2, (6, 2), 50, &CodingOptions { lossless_alpha: true, ..Default::default() }).unwrap();
+    let decoded = image_decompression::<u8>(&stream, 2, (6, 2), &CodingOptions { lossless_alpha: true, ..Default::default() }).unwrap();
+
+    assert!(decoded.iter().skip(1).step_by(2).eq(image.iter().skip(1).step_by(2)));
+    assert!(matches!(image_decompression::<u8>(&stream[..stream.len()-1], 2, (6, 2), &CodingOptions { lossless_alpha: true, ..Default::default() }), Err(YamakagashiError::UnitSizeMismatch { .. }) | Err(YamakagashiError::TruncatedStream { .. })));
+    assert!(crate::compression::image_compression(&image, 3, (4, 2), 50, &CodingOptions { lossless_alpha: true, ..Default::default() }).is_err());
+}
+#[test]
+fn sixteen_bit_test() {
+    // 40x2 gray ramp over the whole 16-bit range, a record forecast for 8-bit would overflow here
+    let image: Vec<u16> = (0..80u32).map(|i| (i % 40 * 1680) as u16).collect();
+
+    let stream = crate::compression::image_compression(&image, 1, (40, 2), 95, &CodingOptions::default()).unwrap();
+    let decoded = image_decompression::<u16>(&stream, 1, (40, 2), &CodingOptions::default()).unwrap();
+
+    let max_difference = decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+    assert!(max_difference < 1 << 9, "max difference {max_difference}");
+}
+#[test]
+fn float_test() {
+    // 30x2 HDR ramp from 1e-3 to 1e4, no sample range to round to
+    let image: Vec<f32> = (0..60).map(|i| 10f32.powf((i % 30) as f32 * 7.0 / 29.0 - 3.0)).collect();
+
+    let stream = crate::compression::image_compression(&image, 1, (30, 2), 99, &CodingOptions::default()).unwrap();
+    let decoded = image_decompression::<f32>(&stream, 1, (30, 2), &CodingOptions::default()).unwrap();
+
+    let max_relative_error = decoded.iter().zip(image.iter()).map(|(&a, &b)| (a - b).abs() / b).fold(0f32, f32::max);
+    assert!(max_relative_error < 0.2, "max relative error {max_relative_error}");
+}
+#[test]
+fn color_transform_test() {
+    // 20x2 warm gradient, B, G and R move together so chroma is almost flat
+    let image: Vec<u8> = (0..40u8).flat_map(|i| [i % 20 * 5, i % 20 * 8 + 20, i % 20 * 10 + 40]).collect();
+
+    for transform in [ColorTransform::YCoCgR, ColorTransform::YCbCr] {
+        let stream = crate::compression::image_compression(&image, 3, (20, 2), 95, &CodingOptions { color_transform: transform, ..Default::default() }).unwrap();
+        let decoded = image_decompression::<u8>(&stream, 3, (20, 2), &CodingOptions { color_transform: transform, ..Default::default() }).unwrap();
+
+        let max_difference = decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+        assert!(max_difference < 8, "{transform:?} max difference {max_difference}");
+    }
+    assert!(crate::compression::image_compression(&image, 1, (60, 2), 95, &CodingOptions { color_transform: ColorTransform::YCbCr, ..Default::default() }).is_err());
+}
+#[test]
+fn subsampling_test() {
+    // 21x5 color with chroma waving across, odd sizes leave a half covered chroma sample at the right and the top
+    let size = (21, 5);
+    let image: Vec<u8> = (0..105u32).flat_map(|i| {
+        let (x, y) = (i % 21, i / 21);
+        let wave = 60.0 * (x as f32 / 4.0).sin();
+        [(x * 6 + y * 4) as u8, (120.0 + wave) as u8, (140.0 - wave) as u8]
+    }).collect();
+    let max_difference = |subsampling| {
+        let stream = crate::compression::image_compression(&image, 3, size, 99, &CodingOptions { color_transform: ColorTransform::YCbCr, subsampling, ..Default::default() }).unwrap();
+        let decoded = image_decompression::<u8>(&stream, 3, size, &CodingOptions { color_transform: ColorTransform::YCbCr, subsampling, ..Default::default() }).unwrap();
+        (stream.len(), decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap())
+    };
+
+    // a unit costs its coeffs, so chroma takes fewer of them only where its waves are sampled fewer times
+    let (full_size, full_difference) = max_difference(ChromaSubsampling::Yuv444);
+    for subsampling in [ChromaSubsampling::Yuv422, ChromaSubsampling::Yuv420] {
+        let (stream_size, difference) = max_difference(subsampling);
+        assert!(stream_size < full_size);
+        assert!(difference <= full_difference + 4, "{subsampling:?} max difference {difference}, {full_difference} without subsampling");
+    }
+    assert!(crate::compression::image_compression(&image, 3, size, 95, &CodingOptions { subsampling: ChromaSubsampling::Yuv420, ..Default::default() }).is_err());
+}
+#[test]
+fn shared_units_test() {
+    // 30x3 color with steps at the same columns in every plane, chroma is subsampled so only luma takes the shared sizes
+    let image: Vec<u8> = (0..90u32).flat_map(|i| { let step = [40, 180, 90, 220, 10][(i % 30 / 6) as usize]; [step as u8, (step / 2 + 20) as u8, (255 - step) as u8] }).collect();
+
+    for subsampling in [ChromaSubsampling::Yuv444, ChromaSubsampling::Yuv422] {
+        let own = CodingOptions { color_transform: ColorTransform::YCoCgR, subsampling, ..Default::default() };
+        let shared = CodingOptions { shared_units: true, ..own };
+        let own_stream = crate::compression::image_compression(&image, 3, (30, 3), 95, &own).unwrap();
+        let shared_stream = crate::compression::image_compression(&image, 3, (30, 3), 95, &shared).unwrap();
+
+        // luma alone gains nothing from sharing
+        match subsampling {
+            ChromaSubsampling::Yuv444 => assert!(shared_stream.len() < own_stream.len()),
+            _ => assert_eq!(shared_stream.len(), own_stream.len()),
+        }
+        let decoded = image_decompression::<u8>(&shared_stream, 3, (30, 3), &shared).unwrap();
+        let max_difference = decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+        assert!(max_difference < 16, "{subsampling:?} max difference {max_difference}");
+        assert!(image_decompression::<u8>(&shared_stream[..shared_stream.len()-2], 3, (30, 3), &shared).is_err());
+    }
+}
+#[test]
+fn inter_row_test() {
+    // 96x64 color of bars drifting slowly down the image
+    let image: Vec<u8> = (0..96 * 64).flat_map(|i| {
+        let (x, y) = ((i % 96) as f32, (i / 96) as f32);
+        let bar = if (i % 96 / 8) % 2 == 0 { 60.0 } else { 190.0 };
+        [bar + y * 0.2, 255.0 - bar, bar * 0.5 + x].map(|value| value as u8)
+    }).collect();
+
+    let own = CodingOptions { color_transform: ColorTransform::YCoCgR, ..Default::default() };
+    let inter_row = CodingOptions { inter_row: true, ..own };
+    let own_stream = crate::bitmap_to_yamakagashi(image.clone(), 3, (96, 64), 70, &own).unwrap();
+    let inter_row_stream = crate::bitmap_to_yamakagashi(image.clone(), 3, (96, 64), 70, &inter_row).unwrap();
+    assert!(inter_row_stream.len() < own_stream.len(), "{} against {}", inter_row_stream.len(), own_stream.len());
+
+
+    // deltas round, but no more than the units themselves
+    let max_difference = |stream: Vec<u8>, options: &CodingOptions| {
+        let decoded = crate::yamakagashi_to_bitmap::<u8>(stream, 3, (96, 64), options).unwrap();
+        decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap()
+    };
+    let (own_difference, inter_row_difference) = (max_difference(own_stream, &own), max_difference(inter_row_stream, &inter_row));
+    assert!(inter_row_difference <= own_difference + 4, "max difference {inter_row_difference} against {own_difference}");
+
+    // the first row has no row above to take unit sizes from
+    assert!(matches!(image_decompression::<u8>(&[0, 1, 0x40, 0x00, 0, 0], 1, (2, 1), &CodingOptions { inter_row: true, ..Default::default() }), Err(YamakagashiError::UnitSizeMismatch { row: 0, .. })));
+}
+#[test]
+fn patches_test() {
+    // 80x70 color, a smooth field with a sharp block, root squares are cut short at the right and the top
+    let image: Vec<u8> = (0..80 * 70).flat_map(|i| {
+        let (x, y) = ((i % 80) as f32, (i / 80) as f32);
+        if (30.0..38.0).contains(&x) && (24.0..32.0).contains(&y) { [255, 0, 0] } else { [x * 1.5 + y, 128.0 + 60.0 * (y / 30.0).sin(), 200.0 - x - y * 0.5].map(|value| value as u8) }
+    }).collect();
+
+    for subsampling in [ChromaSubsampling::Yuv444, ChromaSubsampling::Yuv420] {
+        let rows = CodingOptions { color_transform: ColorTransform::YCoCgR, subsampling, ..Default::default() };
+        let patches = CodingOptions { patches: true, ..rows };
+        let rows_stream = crate::bitmap_to_yamakagashi(image.clone(), 3, (80, 70), 90, &rows).unwrap();
+        let patches_stream = crate::bitmap_to_yamakagashi(image.clone(), 3, (80, 70), 90, &patches).unwrap();
+        assert!(patches_stream.len() < rows_stream.len(), "{subsampling:?} {} against {}", patches_stream.len(), rows_stream.len());
+
+        // a patch is never farther from a sample than a turning point, so the block is what's lost most
+        let max_difference = |stream: Vec<u8>, options: &CodingOptions| {
+            let decoded = crate::yamakagashi_to_bitmap::<u8>(stream, 3, (80, 70), options).unwrap();
+            decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap()
+        };
+        let (rows_difference, patches_difference) = (max_difference(rows_stream, &rows), max_difference(patches_stream, &patches));
+        assert!(patches_difference <= rows_difference + 8, "{subsampling:?} max difference {patches_difference} against {rows_difference}");
+    }
+
+    // a float patch carries its magnitude
+    let image: Vec<f32> = (0..20 * 10).map(|i| 1e3 + (i % 20) as f32 * 10.0 + (i / 20) as f32).collect();
+    let stream = crate::compression::image_compression(&image, 1, (20, 10), 99, &CodingOptions { patches: true, ..Default::default() }).unwrap();
+    let decoded = image_decompression::<f32>(&stream, 1, (20, 10), &CodingOptions { patches: true, ..Default::default() }).unwrap();
+    assert!(decoded.iter().zip(&image).all(|(a, b)| (a - b).abs() < 8.0));
+
+    // tags past the last degree, and a split of a single sample
+    assert!(matches!(image_decompression::<u8>(&[0, 9, 0, 0], 1, (1, 1), &CodingOptions { patches: true, ..Default::default() }), Err(YamakagashiError::BadPatchNode { tag: 9 })));
+    assert!(matches!(image_decompression::<u8>(&[0, 0, 0, 0], 1, (1, 1), &CodingOptions { patches: true, ..Default::default() }), Err(YamakagashiError::BadPatchNode { tag: 0 })));
+    assert!(crate::compression::image_compression(&image, 1, (20, 10), 99, &CodingOptions { patches: true, inter_row: true, ..Default::default() }).is_err());
+}
+#[test]
+fn scan_test() {
+    // 90x40 color of vertical stripes, each column is one unit but each row crosses every stripe
+    let image: Vec<u8> = (0..90 * 40).flat_map(|i| {
+        let (x, y) = (i % 90, (i / 90) as f32);
+        let stripe = if x / 6 % 2 == 0 { 40.0 } else { 180.0 } + 40.0 * (y / 9.0).sin();
+        [stripe, stripe * 0.5 + 30.0, 255.0 - stripe].map(|value| value as u8)
+    }).collect();
+
+    let rows = CodingOptions { color_transform: ColorTransform::YCoCgR, subsampling: ChromaSubsampling::Yuv422, ..Default::default() };
+    let columns = CodingOptions { scan: ScanDirection::Columns, ..rows };
+    let rows_stream = crate::bitmap_to_yamakagashi(image.clone(), 3, (90, 40), 90, &rows).unwrap();
+    let columns_stream = crate::bitmap_to_yamakagashi(image.clone(), 3, (90, 40), 90, &columns).unwrap();
+    assert!(columns_stream.len() < rows_stream.len(), "{} against {}", columns_stream.len(), rows_stream.len());
+    let (chosen, _) = crate::bitmap_to_yamakagashi_any_scan(image.clone(), 3, (90, 40), 90, &rows).unwrap();
+    assert_eq!(chosen.scan, ScanDirection::Columns);
+
+    // columns is rows of the transposed image, and the decoder turns it back
+    let transposed = transpose(&image, 3, (90, 40));
+    assert_eq!(crate::bitmap_to_yamakagashi(transposed.clone(), 3, (40, 90), 90, &rows).unwrap(), columns_stream);
+    let decoded = crate::yamakagashi_to_bitmap::<u8>(columns_stream.clone(), 3, (90, 40), &columns).unwrap();
+    assert_eq!(transpose(&decoded, 3, (90, 40)), crate::yamakagashi_to_bitmap::<u8>(columns_stream, 3, (40, 90), &rows).unwrap());
+
+    // transposing twice is nothing
+    assert_eq!(transpose(&transposed, 3, (40, 90)), image);
+
+    for name in ScanDirection::NAMES {
+        assert_eq!(ScanDirection::from_name(name).unwrap().name(), name);
+    }
+}
+#[test]
+fn region_test() {
+    // 75x53 color with alpha, regions of odd sizes at the edges and inside
+    let image: Vec<u8> = (0..75 * 53).flat_map(|i| {
+        let (x, y) = ((i % 75) as f32, (i / 75) as f32);
+        [x * 2.0 + y, 128.0 + 90.0 * (y / 11.0).sin(), 200.0 - x - y, (x * y) % 256.0].map(|value| value as u8)
+    }).collect();
+    let regions = [(0, 0, 75, 53), (0, 0, 1, 1), (74, 52, 1, 1), (13, 7, 31, 29), (40, 1, 35, 52), (3, 50, 70, 3)];
+
+    let base = CodingOptions { lossless_alpha: true, color_transform: ColorTransform::YCoCgR, ..Default::default() };
+    let all_options = [
+        CodingOptions { subsampling: ChromaSubsampling::Yuv420, inter_row: true, ..base },
+        CodingOptions { subsampling: ChromaSubsampling::Yuv422, patches: true, scan: ScanDirection::Columns, ..base },
+        CodingOptions { shared_units: true, ..base },
+    ];
+    for options in all_options {
+        let stream = crate::compression::image_compression(&image, 4, (75, 53), 80, &options).unwrap();
+        let decoded = image_decompression::<u8>(&stream, 4, (75, 53), &options).unwrap();
+        for region in regions {
+            assert_eq!(region_decompression::<u8>(&stream, 4, (75, 53), &options, region).unwrap(), crate::tiling::crop(&decoded, 4, 75, region), "{options:?} {region:?}");
+        }
+    }
+
+    // tiles the region doesn't touch aren't even unpacked
+    let mut tiles = crate::bitmap_to_yamakagashi_tiles(&image, 4, (75, 53), 80, &base, (32, 16), false).unwrap();
+    let decoded = crate::yamakagashi_tiles_to_bitmap::<u8>(&tiles, 4, (75, 53), &base, (32, 16)).unwrap();
+    tiles[0].data.clear();
+    assert_eq!(crate::decode_region::<u8>(&tiles, 4, (75, 53), &base, (32, 16), (30, 20, 40, 30)).unwrap(), crate::tiling::crop(&decoded, 4, 75, (30, 20, 40, 30)));
+    assert!(matches!(crate::decode_region::<u8>(&tiles, 4, (75, 53), &base, (32, 16), (70, 20, 6, 30)), Err(YamakagashiError::RegionOutOfImage { .. })));
+}
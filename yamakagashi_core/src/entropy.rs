@@ -0,0 +1,240 @@
+//! entropy backend packs the bytes of units into the stream stored in the file, and unpacks them
+//!
+//! xz has always been the backend, zstd codes much faster for a little more size, brotli sits between them,
+//! none stores the bytes as they are, to see what the units alone cost,
+//! range models the records of units themselves and needs no level, see range_coder.
+//! the level of a backend only matters to the encoder, the header records the backend alone.
+mod range_coder;
+use std::io::{self, Read, Write};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use crate::error::{Result, YamakagashiError};
+
+pub trait EntropyBackend {
+    // number recorded in the header
+    fn id(&self) -> u8;
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>>;
+    // limit: the most bytes the image can take, a stream unpacking to more is refused
+    fn decompress(&self, packed: &[u8], limit: usize) -> Result<Vec<u8>>;
+}
+
+// reads up to one byte over limit, so a stream of exactly limit bytes is told from a longer one
+fn read_at_most(backend: &'static str, reader: impl Read, limit: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut bytes).map_err(|why| YamakagashiError::Entropy(backend, why))?;
+    check_limit(backend, bytes.len(), limit)?;
+    Ok(bytes)
+}
+
+fn check_limit(backend: &'static str, length: usize, limit: usize) -> Result<()> {
+    if length > limit {
+        return Err(YamakagashiError::Entropy(backend, io::Error::new(io::ErrorKind::InvalidData, format!("stream unpacks to more than the {limit} bytes the image can take"))));
+    }
+    Ok(())
+}
+
+// preset 0 to 9
+pub struct Xz { pub preset: u32 }
+// level 1 to 22
+pub struct Zstd { pub level: i32 }
+// quality 0 to 11
+pub struct Brotli { pub quality: u32 }
+pub struct Raw;
+pub struct RangeCoder;
+
+impl EntropyBackend for Xz {
+
+    fn id(&self) -> u8 { 0 }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut xz = XzEncoder::new(Vec::new(), self.preset);
+        xz.write_all(bytes).and_then(|_| xz.finish()).map_err(|why| YamakagashiError::Entropy("xz", why))
+    }
+
+    fn decompress(&self, packed: &[u8], limit: usize) -> Result<Vec<u8>> {
+        read_at_most("xz", XzDecoder::new(packed), limit)
+    }
+}
+
+impl EntropyBackend for Zstd {
+
+    fn id(&self) -> u8 { 1 }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        zstd::stream::encode_all(bytes, self.level).map_err(|why| YamakagashiError::Entropy("zstd", why))
+    }
+
+    fn decompress(&self, packed: &[u8], limit: usize) -> Result<Vec<u8>> {
+        read_at_most("zstd", zstd::stream::read::Decoder::new(packed).map_err(|why| YamakagashiError::Entropy("zstd", why))?, limit)
+    }
+}
+
+impl EntropyBackend for Brotli {
+
+    fn id(&self) -> u8 { 2 }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        // 4 KiB buffer, 4 MiB window
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, self.quality, 22);
+        brotli.write_all(bytes).and_then(|_| brotli.flush()).map_err(|why| YamakagashiError::Entropy("brotli", why))?;
+        Ok(brotli.into_inner())
+    }
+
+    fn decompress(&self, packed: &[u8], limit: usize) -> Result<Vec<u8>> {
+        read_at_most("brotli", brotli::Decompressor::new(packed, 4096), limit)
+    }
+}
+
+impl EntropyBackend for Raw {
+
+    fn id(&self) -> u8 { 3 }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+
+    fn decompress(&self, packed: &[u8], limit: usize) -> Result<Vec<u8>> {
+        check_limit("none", packed.len(), limit)?;
+        Ok(packed.to_vec())
+    }
+}
+
+impl EntropyBackend for RangeCoder {
+
+    fn id(&self) -> u8 { 4 }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        range_coder::compress(bytes)
+    }
+
+    fn decompress(&self, packed: &[u8], limit: usize) -> Result<Vec<u8>> {
+        range_coder::decompress(packed, limit)
+    }
+}
+
+// the backend of an image and its level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entropy {
+    Xz(u32),
+    Zstd(i32),
+    Brotli(u32),
+    None,
+    Range,
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Entropy::Xz(6)
+    }
+}
+
+impl Entropy {
+
+    // names accepted by --entropy
+    pub const NAMES: [&'static str; 5] = ["xz", "zstd", "brotli", "none", "range"];
+
+    // at the default level of each backend
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xz" => Some(Entropy::Xz(6)),
+            "zstd" => Some(Entropy::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            "brotli" => Some(Entropy::Brotli(11)),
+            "none" => Some(Entropy::None),
+            "range" => Some(Entropy::Range),
+            _ => None,
+        }
+    }
+
+    // the same backend at level, none and range have no level to take
+    pub fn with_level(self, level: u32) -> Self {
+        match self {
+            Entropy::Xz(_) => Entropy::Xz(level),
+            Entropy::Zstd(_) => Entropy::Zstd(level.min(i32::MAX as u32) as i32),
+            Entropy::Brotli(_) => Entropy::Brotli(level),
+            Entropy::None | Entropy::Range => self,
+        }
+    }
+
+    // the same backend at a level fast enough to try ways of writing bytes with, never slower than self
+    pub fn fast(self) -> Self {
+        match self {
+            Entropy::Xz(_) => Entropy::Xz(0),
+            Entropy::Zstd(level) => Entropy::Zstd(level.min(3)),
+            Entropy::Brotli(quality) => Entropy::Brotli(quality.min(1)),
+            Entropy::None | Entropy::Range => self,
+        }
+    }
+
+    // levels the backend knows
+    pub fn check(self) -> Result<()> {
+        let fits = match self {
+            Entropy::Xz(preset) => preset <= 9,
+            Entropy::Zstd(level) => (1..=22).contains(&level),
+            Entropy::Brotli(quality) => quality <= 11,
+            Entropy::None | Entropy::Range => true,
+        };
+        if !fits {
+            return Err(YamakagashiError::UnsupportedHeader(format!("{self:?} is out of the levels of the backend")));
+        }
+        Ok(())
+    }
+
+    pub fn backend(self) -> Box<dyn EntropyBackend> {
+        match self {
+            Entropy::Xz(preset) => Box::new(Xz { preset }),
+            Entropy::Zstd(level) => Box::new(Zstd { level }),
+            Entropy::Brotli(quality) => Box::new(Brotli { quality }),
+            Entropy::None => Box::new(Raw),
+            Entropy::Range => Box::new(RangeCoder),
+        }
+    }
+
+    // number recorded in the header
+    pub fn id(self) -> u8 {
+        match self {
+            Entropy::Xz(_) => 0,
+            Entropy::Zstd(_) => 1,
+            Entropy::Brotli(_) => 2,
+            Entropy::None => 3,
+            Entropy::Range => 4,
+        }
+    }
+
+    // at the default level, a decoder needs none
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Entropy::default()),
+            1 => Ok(Entropy::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            2 => Ok(Entropy::Brotli(11)),
+            3 => Ok(Entropy::None),
+            4 => Ok(Entropy::Range),
+            _ => Err(YamakagashiError::UnsupportedHeader(format!("unknown entropy backend {id}"))),
+        }
+    }
+}
+
+// bytes after the backend, to choose between ways of writing them
+pub fn packed_size(entropy: Entropy, bytes: &[u8]) -> usize {
+    entropy.backend().compress(bytes).map_or(usize::MAX, |packed| packed.len())
+}
+
+#[test]
+fn entropy_test() {
+    let bytes: Vec<u8> = (0..5000u32).flat_map(|i| ((i * i / 7) as u16 & 0x3FF).to_be_bytes()).collect();
+    for name in Entropy::NAMES {
+        let entropy = Entropy::from_name(name).unwrap();
+        assert_eq!(Entropy::from_id(entropy.id()).unwrap(), entropy);
+        let packed = entropy.backend().compress(&bytes).unwrap();
+        assert_eq!(entropy.backend().id(), entropy.id());
+        assert!(entropy.fast().id() == entropy.id() && entropy.fast().check().is_ok());
+        assert_eq!(entropy.backend().decompress(&packed, bytes.len()).unwrap(), bytes, "{name}");
+        // one byte more than the image can take is refused instead of unpacked
+        assert!(matches!(entropy.backend().decompress(&packed, bytes.len() - 1), Err(YamakagashiError::Entropy(..))), "{name}");
+        if entropy != Entropy::None { assert!(packed.len() < bytes.len(), "{name} {}", packed.len()); }
+    }
+
+    assert_eq!((Entropy::Zstd(1).fast(), Entropy::Brotli(0).fast(), Entropy::Brotli(11).fast()), (Entropy::Zstd(1), Entropy::Brotli(0), Entropy::Brotli(1)));
+    assert!(Entropy::Xz(10).check().is_err() && Entropy::Zstd(0).check().is_err() && Entropy::Brotli(12).check().is_err());
+    assert!(matches!(Entropy::Zstd(3).backend().decompress(&[1, 2, 3], 100), Err(YamakagashiError::Entropy("zstd", _))));
+    assert!(matches!(Entropy::from_id(5), Err(YamakagashiError::UnsupportedHeader(_))));
+}
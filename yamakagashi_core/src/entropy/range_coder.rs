@@ -0,0 +1,533 @@
+//! range coder packs the u16 words of units with adaptive binary models instead of a generic compressor
+//!
+//! the stream is read the way units lie in rows, a unit size, a count of coefficients and as many records,
+//! so a count is modelled by the size of its unit and a record by its index in the unit and the size of the unit.
+//! a match guesses every word as the one which followed the last words when they were seen before,
+//! or as the word of the unit as many samples back as the last match reached, which is mostly the row above,
+//! a row repeating the row above costs a bit a word.
+//! a unit size it misses is guessed as the size which followed the last unit or the last size before,
+//! a record it misses as the record which followed the last record before, then looked up in the recent records,
+//! and coded as the difference, in order of values, to the guess or to the record of the same index in the unit before,
+//! whichever missed by less lately.
+//! a record nothing predicts is coded as its sign, 6-bit exponent and the top of its 9-bit mantissa, each in their own contexts,
+//! the last record of a unit apart as it's never zero.
+//! other layouts (row modes, shared unit sizes, patches, float magnitudes, raw alpha, trailing zeros, varint sizes) round-trip all the same,
+//! their words just land in contexts made for something else and pack worse.
+//! packed bytes are the length of the stream u32 and the range coded bits, coded as LZMA codes its bits.
+use std::collections::HashMap;
+use std::io;
+use crate::error::{Result, YamakagashiError};
+
+// probabilities of a 0 bit are 11-bit, they move by 1/32 of the distance after every bit
+const PROBABILITY_BITS: u32 = 11;
+const HALF: u16 = 1 << (PROBABILITY_BITS - 1);
+const ADAPTATION: u32 = 5;
+// range is renormalized below 2^24
+const TOP: u32 = 1 << 24;
+
+// contexts of records, coefficient index and bit length of the unit size are clamped to them
+const INDICES: usize = 16;
+const SIZES: usize = 8;
+// bit length of a u16 unit size is 0 to 16
+const LENGTHS: usize = 17;
+// numbers of words which have to be seen again before the words after them are guessed, the longer first,
+// and lengths of matches told apart
+const MATCH_ORDERS: [usize; 2] = [8, 4];
+const MATCHES: usize = 16;
+// distinct records kept to be told by their place
+const RECENT: usize = 8;
+
+// the encoder takes bit and gives it back, the decoder ignores bit and gives what it reads
+trait BitCoder {
+    fn bit(&mut self, probability: &mut u16, bit: bool) -> bool;
+}
+
+struct Encoder {
+    low: u64,
+    range: u32,
+    // byte waiting for a carry, and the 0xFF bytes behind it
+    cache: u8,
+    cache_size: u64,
+    packed: Vec<u8>,
+}
+
+impl Encoder {
+
+    fn new(packed: Vec<u8>) -> Self {
+        Encoder { low: 0, range: u32::MAX, cache: 0, cache_size: 1, packed }
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || self.low >> 32 != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            for _ in 0..self.cache_size {
+                self.packed.push(byte.wrapping_add(carry));
+                byte = 0xFF;
+            }
+            self.cache_size = 0;
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        (0..5).for_each(|_| self.shift_low());
+        self.packed
+    }
+}
+
+impl BitCoder for Encoder {
+
+    fn bit(&mut self, probability: &mut u16, bit: bool) -> bool {
+        let bound = (self.range >> PROBABILITY_BITS) * *probability as u32;
+        if bit {
+            self.low += bound as u64;
+            self.range -= bound;
+            *probability -= *probability >> ADAPTATION;
+        } else {
+            self.range = bound;
+            *probability += ((1 << PROBABILITY_BITS) - *probability) >> ADAPTATION;
+        }
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+        bit
+    }
+}
+
+struct Decoder<'a> {
+    code: u32,
+    range: u32,
+    packed: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Decoder<'a> {
+
+    fn new(packed: &'a [u8]) -> Self {
+        let mut decoder = Decoder { code: 0, range: u32::MAX, packed, index: 0 };
+        (0..5).for_each(|_| decoder.code = (decoder.code << 8) | decoder.next_byte() as u32);
+        decoder
+    }
+
+    // past the end reads zeros, overrun tells a cut stream from a whole one
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.packed.get(self.index).copied().unwrap_or(0);
+        self.index += 1;
+        byte
+    }
+
+    fn overrun(&self) -> bool {
+        self.index > self.packed.len()
+    }
+}
+
+impl BitCoder for Decoder<'_> {
+
+    fn bit(&mut self, probability: &mut u16, _: bool) -> bool {
+        let bound = (self.range >> PROBABILITY_BITS) * *probability as u32;
+        let bit = self.code >= bound;
+        if bit {
+            self.code -= bound;
+            self.range -= bound;
+            *probability -= *probability >> ADAPTATION;
+        } else {
+            self.range = bound;
+            *probability += ((1 << PROBABILITY_BITS) - *probability) >> ADAPTATION;
+        }
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte() as u32;
+        }
+        bit
+    }
+}
+
+// bits of value from the top, each under the probability of the bits above it, probabilities has 2^bits
+fn tree(coder: &mut impl BitCoder, probabilities: &mut [u16], bits: u32, value: u32) -> u32 {
+    let mut node = 1;
+    for i in (0..bits).rev() {
+        node = (node << 1) | coder.bit(&mut probabilities[node], (value >> i) & 1 == 1) as usize;
+    }
+    node as u32 - (1 << bits)
+}
+
+fn bit_length(value: u16) -> usize {
+    (u16::BITS - value.leading_zeros()) as usize
+}
+
+// bit length of value under lengths and the bits below its top under the probabilities of that length, gives the length too
+fn number(coder: &mut impl BitCoder, lengths: &mut [u16; 32], bits: &mut [[u16; 16]; LENGTHS], value: u16) -> (u16, usize) {
+    let length = tree(coder, lengths, 5, bit_length(value) as u32) as usize;
+    // the top bit of a length is 1
+    let mut number = (length != 0) as u16;
+    for i in (0..length.clamp(1, 16) - 1).rev() {
+        number = (number << 1) | coder.bit(&mut bits[length.min(LENGTHS - 1)][i], (value >> i) & 1 == 1) as u16;
+    }
+    (number, length.min(LENGTHS - 1))
+}
+
+// what the next word of the stream is
+#[derive(Clone, Copy, PartialEq)]
+enum Word {
+    UnitSize,
+    Count,
+    Record,
+}
+
+// where the stream is and what has been learned, the same for both ways
+struct Model {
+    next: Word,
+    // records left in the current unit, its size and the index of the next record
+    remaining: u16,
+    unit_size: u16,
+    index: usize,
+    previous_size: u16,
+    previous_length: usize,
+    // records of the unit before, by index
+    previous_unit: Vec<u16>,
+    previous_delta_length: [usize; INDICES],
+    // recent bit lengths of the misses of a missed guess and of the unit before, the closer one predicts
+    misses: [[u32; INDICES]; 2],
+    // words so far, where the last words of each order were seen before, and the word after them as a guess
+    history: Vec<u16>,
+    seen: [HashMap<u64, usize>; MATCH_ORDERS.len()],
+    pointer: Option<usize>,
+    // the order of words which found the pointer, or the unit at the sample distance
+    source: usize,
+    match_length: usize,
+    matched: [[[u16; MATCHES]; 3]; MATCH_ORDERS.len() + 1],
+    // where the last unit starts, samples of the units before the next and where each unit starts by them,
+    // and samples between a unit and the unit its match started on
+    unit_start: usize,
+    samples: u64,
+    units: Vec<(u64, usize)>,
+    distance: Option<u64>,
+    // the unit size which last followed the last unit, or a unit of the last size, and whether the last guess was right
+    last_unit: u64,
+    size_after_unit: HashMap<u64, u16>,
+    size_after_size: HashMap<u16, u16>,
+    size_hit: bool,
+    size_guessed: [[u16; 2]; 2],
+    // the record which last followed the last record in a unit of this size, and whether it was right last time
+    last_record: u16,
+    next_record: HashMap<(u16, u16), u16>,
+    next_record_hit: bool,
+    next_record_guessed: [[u16; 2]; 2],
+    // the last distinct records, the latest first
+    recent: Vec<u16>,
+    recent_hit: [u16; 2],
+    recent_index: [[u16; RECENT]; 2],
+    // bit length of a unit size by the bit length of the one before, then the bits below its top
+    length: [[u16; 32]; LENGTHS],
+    size_bits: [[u16; 16]; LENGTHS],
+    // bit length of a count by the bit length of its unit size, then the bits below its top
+    count_length: [[u16; 32]; LENGTHS],
+    count_bits: [[u16; 16]; LENGTHS],
+    same: [u16; INDICES],
+    sign: [[u16; INDICES]; 2],
+    delta_length: Vec<[u16; 32]>,
+    delta_bits: [[u16; 32]; 2],
+    zero: [[[u16; SIZES]; INDICES]; 2],
+    sign_alone: [u16; INDICES],
+    exponent: Vec<[u16; 64]>,
+    mantissa_top: [[u16; 8]; INDICES],
+    mantissa_bits: [u16; 6],
+    byte: [u16; 256],
+}
+
+// records as numbers in order of their values, 0x0000 is 0, the sign bit is set on positive records
+fn ordered(record: u16) -> i32 {
+    match record {
+        0 => 0,
+        _ if record & 0x8000 != 0 => (record & 0x7FFF) as i32 + 1,
+        _ => -((record & 0x7FFF) as i32 + 1),
+    }
+}
+
+fn from_ordered(value: i32) -> u16 {
+    match value {
+        0 => 0,
+        1.. => (value - 1) as u16 | 0x8000,
+        _ => (-(value + 1)) as u16 & 0x7FFF,
+    }
+}
+
+fn hash(words: &[u16]) -> u64 {
+    words.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &word| (hash ^ word as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+impl Model {
+
+    fn new() -> Self {
+        Model {
+            next: Word::UnitSize, remaining: 0, unit_size: 0, index: 0, previous_size: 0, previous_length: 0,
+            previous_unit: Vec::new(), previous_delta_length: [0; INDICES], misses: [[0; INDICES]; 2],
+            history: Vec::new(), seen: [HashMap::new(), HashMap::new()], pointer: None, source: 0, match_length: 0,
+            matched: [[[HALF; MATCHES]; 3]; MATCH_ORDERS.len() + 1],
+            unit_start: 0, samples: 0, units: Vec::new(), distance: None,
+            last_unit: 0, size_after_unit: HashMap::new(), size_after_size: HashMap::new(), size_hit: false, size_guessed: [[HALF; 2]; 2],
+            last_record: 0, next_record: HashMap::new(), next_record_hit: false, next_record_guessed: [[HALF; 2]; 2],
+            recent: Vec::new(), recent_hit: [HALF; 2], recent_index: [[HALF; RECENT]; 2],
+            length: [[HALF; 32]; LENGTHS],
+            size_bits: [[HALF; 16]; LENGTHS],
+            count_length: [[HALF; 32]; LENGTHS],
+            count_bits: [[HALF; 16]; LENGTHS],
+            same: [HALF; INDICES],
+            sign: [[HALF; INDICES]; 2],
+            delta_length: vec![[HALF; 32]; 2 * INDICES * SIZES],
+            delta_bits: [[HALF; 32]; 2],
+            zero: [[[HALF; SIZES]; INDICES]; 2],
+            sign_alone: [HALF; INDICES],
+            exponent: vec![[HALF; 64]; INDICES * SIZES],
+            mantissa_top: [[HALF; 8]; INDICES],
+            mantissa_bits: [HALF; 6],
+            byte: [HALF; 256],
+        }
+    }
+
+    fn word(&mut self, coder: &mut impl BitCoder, word: u16) -> u16 {
+        let guess = self.pointer.map(|pointer| self.history[pointer]);
+        let hit = guess.is_some_and(|guess| coder.bit(&mut self.matched[self.source][self.next as usize][self.match_length.min(MATCHES - 1)], word == guess));
+        let word = self.unit_word(coder, guess, hit, word);
+
+        self.history.push(word);
+        // a match which has hit goes on past a single miss
+        (self.pointer, self.match_length) = match self.pointer {
+            Some(pointer) if hit => (Some(pointer + 1), self.match_length + 1),
+            Some(pointer) if self.match_length > 0 => (Some(pointer + 1), 0),
+            _ => (None, 0),
+        };
+        let end = self.history.len();
+        let mut found = None;
+        for (order, &words) in MATCH_ORDERS.iter().enumerate().filter(|&(_, &words)| words <= end) {
+            let pointer = self.seen[order].insert(hash(&self.history[end - words..]), end);
+            found = found.or(pointer.map(|pointer| (order, pointer)));
+        }
+        if let Some((order, pointer)) = found.filter(|_| self.match_length == 0) {
+            (self.pointer, self.source) = (Some(pointer), order);
+        }
+        if self.next == Word::UnitSize {
+            self.unit_ended();
+        }
+        word
+    }
+
+    // a unit ending where the pointer is on a unit start tells how many samples back the image repeats,
+    // a unit starting without a match guesses the unit as many samples back
+    fn unit_ended(&mut self) {
+        let end = self.history.len();
+        self.last_unit = hash(&self.history[self.unit_start..end]);
+        self.samples += self.history[self.unit_start] as u64;
+        self.unit_start = end;
+
+        if let Some(pointer) = self.pointer.filter(|_| self.match_length > 0) {
+            if let Ok(i) = self.units.binary_search_by_key(&pointer, |&(_, start)| start) {
+                self.distance = Some(self.samples - self.units[i].0);
+            }
+        }
+        self.units.push((self.samples, end));
+        if self.match_length == 0 {
+            if let Some(target) = self.distance.and_then(|distance| self.samples.checked_sub(distance)) {
+                let i = self.units.partition_point(|&(samples, _)| samples <= target);
+                if let Some(&(_, start)) = self.units[..i].last().filter(|&&(_, start)| start < end) {
+                    (self.pointer, self.source) = (Some(start), MATCH_ORDERS.len());
+                }
+            }
+        }
+    }
+
+    // guess: the word the match guessed, taken without coding on a hit and predicting the record otherwise
+    fn unit_word(&mut self, coder: &mut impl BitCoder, guess: Option<u16>, hit: bool, word: u16) -> u16 {
+        let guessed = guess.filter(|_| hit);
+        match self.next {
+            Word::UnitSize => {
+                let unit_size = guessed.unwrap_or_else(|| self.unit_size(coder, word));
+                self.size_after_unit.insert(self.last_unit, unit_size);
+                self.size_after_size.insert(self.previous_size, unit_size);
+                (self.next, self.unit_size, self.previous_size) = (Word::Count, unit_size, unit_size);
+                unit_size
+            },
+            Word::Count => {
+                let size = bit_length(self.unit_size);
+                let count = guessed.unwrap_or_else(|| number(coder, &mut self.count_length[size], &mut self.count_bits, word).0);
+                (self.next, self.remaining, self.index) = (if count == 0 { Word::UnitSize } else { Word::Record }, count, 0);
+                count
+            },
+            Word::Record => {
+                let record = guessed.unwrap_or_else(|| self.record(coder, guess, word));
+                if self.index < self.previous_unit.len() {
+                    self.previous_unit[self.index] = record;
+                } else {
+                    self.previous_unit.push(record);
+                }
+                self.next_record.insert((self.last_record, self.unit_size), record);
+                self.last_record = record;
+                if let Some(position) = self.recent.iter().position(|&recent| recent == record) {
+                    self.recent.remove(position);
+                }
+                self.recent.insert(0, record);
+                self.recent.truncate(RECENT);
+                self.remaining -= 1;
+                self.index += 1;
+                if self.remaining == 0 {
+                    self.next = Word::UnitSize;
+                }
+                record
+            },
+        }
+    }
+
+    fn unit_size(&mut self, coder: &mut impl BitCoder, unit_size: u16) -> u16 {
+        let after_unit = self.size_after_unit.get(&self.last_unit).copied();
+        let guess = after_unit.or_else(|| self.size_after_size.get(&self.previous_size).copied());
+        self.size_hit = guess.is_some_and(|guess| coder.bit(&mut self.size_guessed[after_unit.is_some() as usize][self.size_hit as usize], unit_size == guess));
+        match guess.filter(|_| self.size_hit) {
+            Some(guess) => guess,
+            None => {
+                let (unit_size, length) = number(coder, &mut self.length[self.previous_length], &mut self.size_bits, unit_size);
+                self.previous_length = length;
+                unit_size
+            },
+        }
+    }
+
+    // the record which followed the last record before or one of the recent records,
+    // otherwise the difference to the guess of the match which missed it or to the record of the same index in the unit before,
+    // whichever missed by less lately, in order of values
+    fn record(&mut self, coder: &mut impl BitCoder, guess: Option<u16>, record: u16) -> u16 {
+        let (index, missed) = (self.index.min(INDICES - 1), guess.is_some() as usize);
+        if let Some(&next) = self.next_record.get(&(self.last_record, self.unit_size)).filter(|&&next| Some(next) != guess) {
+            self.next_record_hit = coder.bit(&mut self.next_record_guessed[missed][self.next_record_hit as usize], record == next);
+            if self.next_record_hit {
+                return next;
+            }
+        }
+        if !self.recent.is_empty() {
+            let position = self.recent.iter().position(|&recent| recent == record);
+            if coder.bit(&mut self.recent_hit[missed], position.is_some()) {
+                let position = tree(coder, &mut self.recent_index[missed], 3, position.unwrap_or(0) as u32) as usize;
+                return self.recent[position.min(self.recent.len() - 1)];
+            }
+        }
+
+        let before = self.previous_unit.get(self.index).copied().unwrap_or(0);
+        let record = self.record_from(coder, guess.filter(|_| self.misses[0][index] <= self.misses[1][index]), before, record);
+        if let Some(guess) = guess {
+            let miss = |predicted: u16| u32::BITS - (ordered(record) - ordered(predicted)).unsigned_abs().leading_zeros();
+            self.misses[0][index] = self.misses[0][index] * 3 / 4 + miss(guess);
+            self.misses[1][index] = self.misses[1][index] * 3 / 4 + miss(before);
+        }
+        record
+    }
+
+    fn record_from(&mut self, coder: &mut impl BitCoder, guess: Option<u16>, before: u16, record: u16) -> u16 {
+        let (index, missed) = (self.index.min(INDICES - 1), guess.is_some() as usize);
+        let predicted = ordered(guess.unwrap_or(before));
+        // a missed guess is known to differ
+        if missed == 0 && coder.bit(&mut self.same[index], record == from_ordered(predicted)) {
+            self.previous_delta_length[index] = 0;
+            return from_ordered(predicted);
+        }
+
+        if predicted == 0 {
+            return self.fields(coder, record);
+        }
+
+        let delta = ordered(record) - predicted;
+        let sign = coder.bit(&mut self.sign[missed][index], delta < 0);
+        let magnitude = delta.unsigned_abs();
+        let context = (missed * INDICES + index) * SIZES + self.previous_delta_length[index].min(SIZES - 1);
+        let length = tree(coder, &mut self.delta_length[context], 5, u32::BITS - magnitude.leading_zeros()) as usize;
+        self.previous_delta_length[index] = length;
+        // the top bit of a length is 1, the bit under it is learned apart from the rest
+        let mut value = 1u32;
+        for i in (0..length.clamp(1, 32) - 1).rev() {
+            let probability = &mut self.delta_bits[(i + 2 == length) as usize][i];
+            value = (value << 1) | coder.bit(probability, (magnitude >> i) & 1 == 1) as u32;
+        }
+        // a broken stream may run out of the values, it decodes to something wrong but does not overflow
+        let value = value as i32;
+        from_ordered(if sign { predicted.wrapping_sub(value) } else { predicted.wrapping_add(value) })
+    }
+
+    // sign 1 bit, exponent 6 bits, mantissa 9 bits of a record nothing predicts, 0x0000 for zero
+    fn fields(&mut self, coder: &mut impl BitCoder, record: u16) -> u16 {
+        let (index, size) = (self.index.min(INDICES - 1), bit_length(self.unit_size).min(SIZES - 1));
+        if coder.bit(&mut self.zero[(self.remaining == 1) as usize][index][size], record == 0) { return 0; }
+
+        let sign = coder.bit(&mut self.sign_alone[index], record & 0x8000 != 0) as u16;
+        let exponent = tree(coder, &mut self.exponent[index * SIZES + size], 6, (record as u32 >> 9) & 0x3F) as u16;
+        let mut mantissa = tree(coder, &mut self.mantissa_top[index], 3, (record as u32 >> 6) & 0x7) as u16;
+        for i in (0..6).rev() {
+            mantissa = (mantissa << 1) | coder.bit(&mut self.mantissa_bits[i], (record >> i) & 1 == 1) as u16;
+        }
+        (sign << 15) | (exponent << 9) | mantissa
+    }
+}
+
+pub fn compress(bytes: &[u8]) -> Result<Vec<u8>> {
+
+    let length = u32::try_from(bytes.len())
+        .map_err(|_| YamakagashiError::Entropy("range", io::Error::new(io::ErrorKind::InvalidInput, "stream is longer than u32")))?;
+    let mut encoder = Encoder::new(length.to_be_bytes().to_vec());
+    let mut model = Model::new();
+
+    for word in bytes.chunks_exact(2) {
+        model.word(&mut encoder, u16::from_be_bytes([word[0], word[1]]));
+    }
+    if let Some(&byte) = bytes.chunks_exact(2).remainder().first() {
+        tree(&mut encoder, &mut model.byte, 8, byte as u32);
+    }
+
+    Ok(encoder.finish())
+}
+
+// limit: the most bytes the image can take, a recorded length over it is refused
+pub fn decompress(packed: &[u8], limit: usize) -> Result<Vec<u8>> {
+
+    let cut = || YamakagashiError::Entropy("range", io::Error::new(io::ErrorKind::UnexpectedEof, "range coded stream is cut short"));
+    let length = packed.get(..4).map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize).ok_or_else(cut)?;
+    if length > limit {
+        return Err(YamakagashiError::Entropy("range", io::Error::new(io::ErrorKind::InvalidData, format!("stream unpacks to {length} bytes where the image takes {limit} at most"))));
+    }
+    let mut decoder = Decoder::new(&packed[4..]);
+    let mut model = Model::new();
+
+    // not allocated up front, a broken length would ask for gigabytes
+    let mut bytes = Vec::new();
+    while bytes.len() + 1 < length {
+        bytes.extend(model.word(&mut decoder, 0).to_be_bytes());
+        if decoder.overrun() { return Err(cut()); }
+    }
+    if bytes.len() < length {
+        bytes.push(tree(&mut decoder, &mut model.byte, 8, 0) as u8);
+    }
+    if decoder.overrun() { return Err(cut()); }
+
+    Ok(bytes)
+}
+
+#[test]
+fn range_coder_test() {
+    // rows of 40x30 gray, coded into units and packed by both
+    let image: Vec<u8> = (0..40 * 30).map(|i| { let (x, y) = ((i % 40) as f32, (i / 40) as f32); (100.0 + 50.0 * (x / 7.0).sin() + 3.0 * (y * 1.7).cos()) as u8 }).collect();
+    let bytes = crate::compression::image_compression(&image, 1, (40, 30), 90, &crate::CodingOptions::default()).unwrap();
+    let packed = compress(&bytes).unwrap();
+    assert_eq!(decompress(&packed, bytes.len()).unwrap(), bytes);
+    assert!(decompress(&packed, bytes.len() - 1).is_err());
+    let xz = crate::entropy::packed_size(crate::Entropy::Xz(9), &bytes);
+    assert!(packed.len() < xz, "range {} against xz {xz}", packed.len());
+
+    // odd lengths and streams which are not units at all
+    let odd: Vec<u8> = (0..999u32).map(|i| (i * 37 % 251) as u8).collect();
+    assert_eq!(decompress(&compress(&odd).unwrap(), odd.len()).unwrap(), odd);
+    assert_eq!(decompress(&compress(&[]).unwrap(), 0).unwrap(), Vec::<u8>::new());
+    assert!(decompress(&packed[..packed.len() / 2], bytes.len()).is_err());
+    // garbage decodes to something or fails, but never panics
+    let garbage: Vec<u8> = [0, 0, 4, 0].into_iter().chain((0..300u32).map(|i| (i * 97 % 256) as u8)).collect();
+    let _ = decompress(&garbage, usize::MAX);
+}
@@ -0,0 +1,73 @@
+//! YamakagashiError is returned by every public entry point instead of panicking,
+//! so a broken file can't take down the process that decodes it.
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum YamakagashiError {
+    /// stream ended while a unit size or coefficient was still expected
+    TruncatedStream { needed: usize, available: usize },
+    /// unit sizes of a row don't add up to the image width
+    UnitSizeMismatch { row: usize, expected: u32, actual: u32 },
+    /// a unit or patch stores more coeffs than it has samples or terms
+    TooManyCoefficients { count: u32, limit: u32 },
+    /// a patch node has a tag this version doesn't know, or splits a single sample
+    BadPatchNode { tag: u16 },
+    /// bytes are left over after the last unit of the last plane, or after the end of the file
+    TrailingData { remaining: usize },
+    /// a coefficient or a decoded sample doesn't fit the sample range
+    CoefficientOverflow,
+    /// least squares of a unit of this size came to a singular system
+    SingularFit { unit_size: usize },
+    /// a coeff can't be turned into a record, for the reason given
+    UnrecordableCoefficient(&'static str),
+    /// entropy backend, named first, failed to compress or decompress
+    Entropy(&'static str, io::Error),
+    /// stored crc doesn't match the file contents
+    Corrupted { expected: u32, actual: u32 },
+    /// region to decode reaches out of the image
+    RegionOutOfImage { region: (u32, u32, u32, u32), size: (u32, u32) },
+    /// header describes something this build can't handle
+    UnsupportedHeader(String),
+    /// reading or writing a file failed
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, YamakagashiError>;
+
+impl fmt::Display for YamakagashiError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            YamakagashiError::TruncatedStream { needed, available } => write!(f, "truncated stream, needs {needed} bytes but only {available} bytes are available"),
+            YamakagashiError::UnitSizeMismatch { row, expected, actual } => write!(f, "unit size mismatch at row {row}, row size is {expected} but sum of unit size is {actual}"),
+            YamakagashiError::TooManyCoefficients { count, limit } => write!(f, "{count} coefficients are stored where {limit} fit"),
+            YamakagashiError::BadPatchNode { tag } => write!(f, "bad patch node, tag {tag} can't be decoded here"),
+            YamakagashiError::TrailingData { remaining } => write!(f, "{remaining} bytes of trailing data are left over"),
+            YamakagashiError::CoefficientOverflow => write!(f, "coefficient overflow, value doesn't fit the sample range"),
+            YamakagashiError::SingularFit { unit_size } => write!(f, "singular fit, the system of a unit of {unit_size} samples has no solution"),
+            YamakagashiError::UnrecordableCoefficient(why) => write!(f, "coefficient can't be recorded, {why}"),
+            YamakagashiError::Entropy(backend, why) => write!(f, "{backend} failure, {why}"),
+            YamakagashiError::Corrupted { expected, actual } => write!(f, "corrupted file, crc is {actual:08X} but {expected:08X} is recorded"),
+            YamakagashiError::RegionOutOfImage { region: (x, y, width, height), size } => write!(f, "region {width}x{height} at ({x}, {y}) is not in {}x{} image", size.0, size.1),
+            YamakagashiError::UnsupportedHeader(why) => write!(f, "unsupported header, {why}"),
+            YamakagashiError::Io(why) => write!(f, "{why}"),
+        }
+    }
+}
+
+impl error::Error for YamakagashiError {
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            YamakagashiError::Entropy(_, why) | YamakagashiError::Io(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for YamakagashiError {
+
+    fn from(why: io::Error) -> Self {
+        YamakagashiError::Io(why)
+    }
+}
@@ -0,0 +1,79 @@
+//! inter-row prediction lets a row lean on the row above it in the same plane
+//!
+//! unit boundaries: a row takes the turning points of the row above when they still fit,
+//! that is cutting the row there leaves no other turning point, then its unit sizes are not written.
+//! coefficients: a unit at the same place and of the same size as a unit above (co-located) can be coded
+//! as the difference of its coeffs from the coeffs above, which is small on smooth vertical structure.
+//! every row has a u16 row mode telling which of them it uses, the row modes of all rows come ahead of the rows.
+use std::collections::LinkedList;
+use crate::my_float::MyFp48;
+
+// unit sizes are the same as the row above and are not written
+pub const SAME_UNIT_SIZES: u16 = 1;
+// co-located units are differences from the units above
+pub const COEFF_DELTA: u16 = 2;
+
+// a row of units, (unit size, coeffs)
+pub type UnitRow = LinkedList<(u32, Vec<u16>)>;
+
+pub fn same_unit_sizes(row: &UnitRow, above: &UnitRow) -> bool {
+    row.len() == above.len() && row.iter().zip(above).all(|(unit, above_unit)| unit.0 == above_unit.0)
+}
+
+// coeffs of co-located units minus (subtract) or plus (!subtract) the coeffs above, the others as they are
+// records are added and subtracted as values, so a delta keeps the finer steps of a small value
+// header_size: float units have a magnitude ahead of the records, their records only compare under the same magnitude
+// coeffs left out at the end of a unit are zeros, the longer of the two is kept
+pub fn delta_against_above(row: &UnitRow, above: &UnitRow, header_size: usize, subtract: bool) -> UnitRow {
+
+    let mut above_units = above.iter().scan(0u32, |start, (unit_size, coeffs)| {
+        let unit = (*start, *unit_size, coeffs);
+        *start += *unit_size;
+        Some(unit)
+    }).peekable();
+
+    let mut start = 0u32;
+    row.iter().map(|(unit_size, coeffs)| {
+        while above_units.next_if(|&(above_start, _, _)| above_start < start).is_some() {}
+        let coeffs = match above_units.peek() {
+            Some(&(above_start, above_size, above_coeffs))
+                if above_start == start && above_size == *unit_size && coeffs[..header_size] == above_coeffs[..header_size] => {
+                coeffs[..header_size].iter().copied().chain((header_size..coeffs.len().max(above_coeffs.len())).map(|i| {
+                    let coeff_at = |coeffs: &Vec<u16>| MyFp48::from_record_bytes(coeffs.get(i).copied().unwrap_or_default());
+                    let (value, above_value) = (coeff_at(coeffs), coeff_at(above_coeffs));
+                    record_of(if subtract { value - above_value } else { value + above_value })
+                })).collect()
+            },
+            _ => coeffs.clone(),
+        };
+        start += *unit_size;
+        (*unit_size, coeffs)
+    }).collect()
+}
+
+// a value back to a record, saturated like unit_compression does
+fn record_of(value: MyFp48) -> u16 {
+    match value.to_record_bytes_with_forecast(0) {
+        Ok(record) => record,
+        Err("can't express f16, because of this MyFp48 abs is too small") => 0x0000,
+        Err(_) => if value.sign() == 1 { 0x7FFF } else { 0xFFFF },
+    }
+}
+
+// rough size of a row of records, the exponent of a record grows with its value and zero is free
+pub fn record_cost(row: &UnitRow) -> usize {
+    row.iter().flat_map(|(_, coeffs)| coeffs).filter(|&&coeff| coeff != 0).map(|&coeff| (coeff as usize >> 9 & 0x3F) + 1).sum()
+}
+
+#[test]
+fn inter_row_test() {
+    let above_row: UnitRow = [(2, vec![0x3E00, 0x3C00]), (3, vec![0x3E00, 0x3C00, 0])].into_iter().collect();
+    let row: UnitRow = [(2, vec![0x3E01, 0x3C00]), (1, vec![0x3E00]), (2, vec![0x3E00, 0x3C00])].into_iter().collect();
+    let delta = delta_against_above(&row, &above_row, 0, true);
+    // only the first unit is co-located, its delta is a small value and a zero
+    let delta_coeffs: Vec<Vec<u16>> = delta.iter().map(|unit| unit.1.clone()).collect();
+    assert!(delta_coeffs[0][0] >> 9 < 0x3E00 >> 9 && delta_coeffs[0][1] == 0);
+    assert_eq!(delta_coeffs[1..], [vec![0x3E00], vec![0x3E00, 0x3C00]]);
+    assert_eq!(delta_against_above(&delta, &above_row, 0, false), row);
+    assert!(record_cost(&delta) < record_cost(&row));
+}
@@ -2,29 +2,120 @@ mod my_vector;
 mod my_float;
 mod compression;
 mod decompression;
-use std::io::{Read, Write};
-use xz2::read::XzDecoder;
-use xz2::write::XzEncoder;
+mod error;
+mod sample;
+mod color_transform;
+mod subsampling;
+mod coding_options;
+mod inter_row;
+mod scan;
+mod tiling;
+mod parallel;
+mod entropy;
+use parallel::{join, map_slice};
 use compression::image_compression;
-use decompression::image_decompression;
+use decompression::{image_decompression, max_stream_size, region_decompression};
+pub use error::{Result, YamakagashiError};
+pub use sample::Sample;
+pub use color_transform::ColorTransform;
+pub use subsampling::ChromaSubsampling;
+pub use coding_options::CodingOptions;
+pub use scan::ScanDirection;
+pub use entropy::{Entropy, EntropyBackend};
+pub use tiling::{tile_rects, Tile, TileRect};
+use tiling::{check_region, check_tile_size, crop, number_of_tiles, paste};
 
-// compress yamakagashi-bytes by xz
+// compress yamakagashi-bytes by the entropy backend of options
+// samples are u8, u16 or f32, the decoder has to be asked for the same type
+// options: coding tools besides quality, the decoder has to be given the same
 
-pub fn bitmap_to_yamakagashi(bitmap_vec:Vec<u8>, image_size:(u32, u32), quality:i32) -> Vec<u8> {
-    
-    let yamakagashi_bytes:Vec<u8> = image_compression(&bitmap_vec, 3, image_size, quality);
-    
-    let mut xz_yamakagashi = XzEncoder::new(Vec::new(), 6);
-    xz_yamakagashi.write_all(&yamakagashi_bytes).expect("Failed to write data");
-    xz_yamakagashi.finish().expect("Failed to finish compression")
+pub fn bitmap_to_yamakagashi<S: Sample>(bitmap_vec:Vec<S>, number_of_colors:u8, image_size:(u32, u32), quality:i32, options:&CodingOptions) -> Result<Vec<u8>> {
+
+    let yamakagashi_bytes:Vec<u8> = image_compression(&bitmap_vec, number_of_colors, image_size, quality, options)?;
+
+    options.entropy.backend().compress(&yamakagashi_bytes)
+}
+
+// encode along rows and along columns, and keep the smaller
+// returns the options the image data is coded with, options.scan is not looked at
+
+pub fn bitmap_to_yamakagashi_any_scan<S: Sample>(bitmap_vec:Vec<S>, number_of_colors:u8, image_size:(u32, u32), quality:i32, options:&CodingOptions) -> Result<(CodingOptions, Vec<u8>)> {
+
+    let rows = CodingOptions { scan: ScanDirection::Rows, ..*options };
+    let columns = CodingOptions { scan: ScanDirection::Columns, ..*options };
+    let (rows_data, columns_data) = join(
+        || bitmap_to_yamakagashi(bitmap_vec.clone(), number_of_colors, image_size, quality, &rows),
+        || bitmap_to_yamakagashi(bitmap_vec.clone(), number_of_colors, image_size, quality, &columns),
+    );
+    let (rows_data, columns_data) = (rows_data?, columns_data?);
+
+    Ok(if columns_data.len() < rows_data.len() { (columns, columns_data) } else { (rows, rows_data) })
+}
+
+// cut the image into tiles of tile_size and compress each of them on its own
+// auto_scan: each tile keeps the smaller of rows and columns, otherwise all follow options.scan
+
+pub fn bitmap_to_yamakagashi_tiles<S: Sample>(bitmap_vec:&[S], number_of_colors:u8, image_size:(u32, u32), quality:i32, options:&CodingOptions, tile_size:(u32, u32), auto_scan:bool) -> Result<Vec<Tile>> {
+
+    check_tile_size(tile_size)?;
+    map_slice(&tile_rects(image_size, tile_size), |&rect| {
+        let tile_vec = crop(bitmap_vec, number_of_colors, image_size.0, rect);
+        let (coding, data) = if auto_scan {
+            bitmap_to_yamakagashi_any_scan(tile_vec, number_of_colors, (rect.2, rect.3), quality, options)?
+        } else {
+            (*options, bitmap_to_yamakagashi(tile_vec, number_of_colors, (rect.2, rect.3), quality, options)?)
+        };
+        Ok(Tile { scan: coding.scan, data })
+    }).into_iter().collect()
 }
 
-// decompress yamakagashi-bytes by xz
+// decompress yamakagashi-bytes by the entropy backend of options, its level is not looked at
 
-pub fn yamakagashi_to_bitmap(xz_yamakagashi: Vec<u8>, number_of_colors:u8, image_size:(u32, u32)) -> Vec<u8> {
-    
-    let mut yamakagashi_bytes:Vec<u8> = Vec::new();
-    XzDecoder::new(&xz_yamakagashi[..]).read_to_end(&mut yamakagashi_bytes).expect("Failed to read data");
+pub fn yamakagashi_to_bitmap<S: Sample>(packed_yamakagashi: Vec<u8>, number_of_colors:u8, image_size:(u32, u32), options:&CodingOptions) -> Result<Vec<S>> {
 
-    image_decompression(&yamakagashi_bytes, number_of_colors, image_size)
-}
\ No newline at end of file
+    let limit = max_stream_size(number_of_colors, image_size, options);
+    image_decompression(&options.entropy.backend().decompress(&packed_yamakagashi, limit)?, number_of_colors, image_size, options)
+}
+
+// decompress every tile and put them together, options.scan is taken from each tile
+
+pub fn yamakagashi_tiles_to_bitmap<S: Sample>(tiles:&[Tile], number_of_colors:u8, image_size:(u32, u32), options:&CodingOptions, tile_size:(u32, u32)) -> Result<Vec<S>> {
+
+    decode_region(tiles, number_of_colors, image_size, options, tile_size, (0, 0, image_size.0, image_size.1))
+}
+
+// decompress only the tiles region touches, and evaluate only the units of their rows in region
+// region: (x, y, width, height) in pixels of the image data, an image of one stream is one tile of image_size
+// returns the interleaved pixels of region, options.scan is taken from each tile
+
+pub fn decode_region<S: Sample>(tiles:&[Tile], number_of_colors:u8, image_size:(u32, u32), options:&CodingOptions, tile_size:(u32, u32), region:TileRect) -> Result<Vec<S>> {
+
+    check_tile_size(tile_size)?;
+    check_region(region, image_size)?;
+    let expected = number_of_tiles(image_size, tile_size);
+    if tiles.len() != expected {
+        return Err(YamakagashiError::UnsupportedHeader(format!("{} tiles where the image has {expected}", tiles.len())));
+    }
+
+    // every tile is decoded before the region is allocated, a tile checks its size against its own stream
+    let (x, y, width, height) = region;
+    // (tile, its rect, the part of region in it) of the tiles region touches, in pixels of the image
+    let touched: Vec<(&Tile, TileRect, TileRect)> = tiles.iter().zip(tile_rects(image_size, tile_size)).filter_map(|(tile, rect)| {
+        let (left, bottom) = (x.max(rect.0), y.max(rect.1));
+        let (right, top) = ((x + width).min(rect.0 + rect.2), (y + height).min(rect.1 + rect.3));
+        (left < right && bottom < top).then(|| (tile, rect, (left, bottom, right - left, top - bottom)))
+    }).collect();
+    let tile_vecs: Vec<(TileRect, Vec<S>)> = map_slice(&touched, |&(tile, rect, (left, bottom, part_width, part_height))| {
+        let options = CodingOptions { scan: tile.scan, ..*options };
+        let limit = max_stream_size(number_of_colors, (rect.2, rect.3), &options);
+        let tile_vec = region_decompression(&options.entropy.backend().decompress(&tile.data, limit)?, number_of_colors, (rect.2, rect.3), &options, (left - rect.0, bottom - rect.1, part_width, part_height))?;
+        Ok(((left - x, bottom - y, part_width, part_height), tile_vec))
+    }).into_iter().collect::<Result<_>>()?;
+
+    let mut bitmap_vec = vec![S::default(); width as usize * height as usize * number_of_colors as usize];
+    for (rect, tile_vec) in &tile_vecs {
+        paste(&mut bitmap_vec, number_of_colors, width, *rect, tile_vec);
+    }
+
+    Ok(bitmap_vec)
+}
@@ -1,10 +1,9 @@
-/// base_exponent  is  8 bits
-/// extra_exponent is 16 bits
-/// exponent       is 24 bits
-/// 
-/// s1(u16)e8f23
-/// u16*2^8+e8 - 2^23+1 is actual exponent
-
+//! base_exponent  is  8 bits
+//! extra_exponent is 16 bits
+//! exponent       is 24 bits
+//!
+//! s1(u16)e8f23
+//! u16*2^8+e8 - 2^23+1 is actual exponent
 use std::{cmp::Ordering, fmt, ops};
 
 const BASE_MANTISSA_AND_SIGN_MASK: u32 = 0x807F_FFFF;
@@ -40,10 +39,15 @@ impl MyFp48 {
     // pub const NEG_INFINITY: MyFp48 = Self { base: f32::NEG_INFINITY, extra_exponent: 0xFFFF};
     
     // pub fn default() -> Self { MyFp48::ZERO }
+    // exp2, None when exponent is out of the range of MyFp48
+    pub fn checked_exp2(exponent: i32) -> Option<Self> {
+        (-(1 << 23)..=(1 << 23) - 1).contains(&exponent).then(|| Self::exp2(exponent))
+    }
+
     // create new MyFp48 exp2
     pub fn exp2(exponent: i32) -> Self {
 
-        if (exponent < - (1 << 23)) | (((1 << 23) - 1) < exponent) { panic!("exp needs satisfy -(1 << 23) < exp < 2 << 23") }
+        if !(-(1 << 23)..=(1 << 23) - 1).contains(&exponent) { panic!("exp needs satisfy -(1 << 23) < exp < 2 << 23") }
 
         let new_exponent = (exponent + ((1 << 23) - 1)) as u32;
         
@@ -73,6 +77,33 @@ impl MyFp48 {
             Ok(self_to_f32.round() as u8)
         }
     }
+
+    pub fn round_u16(&self) -> Result<u16, ()> {
+
+        let exponent = self.exponent();
+        let mantissa = self.mantissa_and_sign();
+
+        if exponent <= -127 { Ok(0u16) }
+        else if 128 <= exponent { Err(()) }
+        else {
+            let f32_exponent = (exponent + (1 << 7)-1) as u32;
+            let self_to_f32 = f32::from_bits((mantissa.to_bits() & BASE_MANTISSA_AND_SIGN_MASK) | (f32_exponent << 23));
+            Ok(self_to_f32.round() as u16)
+        }
+    }
+    pub fn round_f32(&self) -> Result<f32, ()> {
+
+        let exponent = self.exponent();
+        let mantissa = self.mantissa_and_sign();
+
+        if exponent <= -127 { Ok(0f32.copysign(mantissa)) }
+        else if 128 <= exponent { Err(()) }
+        else {
+            let f32_exponent = (exponent + (1 << 7)-1) as u32;
+            Ok(f32::from_bits((mantissa.to_bits() & BASE_MANTISSA_AND_SIGN_MASK) | (f32_exponent << 23)))
+        }
+    }
+
     // get extended exponent
     pub fn exponent(&self) -> i32 {
         // no biased exponent
@@ -85,9 +116,9 @@ impl MyFp48 {
 
     // convert to record bytes
     // sign 1bit, diff exponent 6bit, fracsion 9bit
-    pub fn to_record_bytes_with_forecast(&self, forecast: i32) -> Result<u16, &str> {
+    pub fn to_record_bytes_with_forecast(self, forecast: i32) -> Result<u16, &'static str> {
  
-        let exponent = self.exponent() + forecast;
+        let exponent = self.exponent().saturating_add(forecast);
         let mantissa_and_sign = self.mantissa_and_sign();
 
         if self.is_zero() { Ok(0x0000) }
@@ -129,9 +160,7 @@ impl MyFp48 {
 
         let base_bits = self.base.to_bits();
         let mantissa_bits = base_bits & BASE_MANTISSA_AND_SIGN_MASK;
-        let mantissa = f32::from_bits(mantissa_bits | 0x3F80_0000);
-
-        mantissa
+        f32::from_bits(mantissa_bits | 0x3F80_0000)
     }
 
     // add
@@ -350,9 +379,8 @@ impl PartialEq for MyFp48 {
 
     fn eq(&self, other: &Self) -> bool {
         
-        if self.is_normal() && other.is_normal() { return self.base == other.base && self.extra_exponent == other.extra_exponent; }
-        else if self.is_zero() && other.is_zero() { return true; }
-        else { return false; }
+        if self.is_normal() && other.is_normal() { self.base == other.base && self.extra_exponent == other.extra_exponent }
+        else { self.is_zero() && other.is_zero() }
     }
 }
 
@@ -427,10 +455,11 @@ fn test_compare() {
 }
 
 #[test]
+#[allow(clippy::excessive_precision)]
 fn test_value_and_round() {
 
     // value
-    let a = MyFp48::new(3.14154052734375);
+    let _a = MyFp48::new(3.14154052734375);
     // assert_eq!(a.to_record_bytes().unwrap(), 3.14154052734375);
 
     let b = 2.25f32.round() as u8;
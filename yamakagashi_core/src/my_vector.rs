@@ -1,7 +1,8 @@
+//! This lib provide VecTool. e.g. dotproduct, sqnorm, and hadamardproduct.
 use std::{fmt, iter::Sum};
-
-/// This lib provide VecTool. e.g. dotproduct, sqnorm, and hadamardproduct.
 use crate::my_float::MyFp48;
+use crate::compression::unit_compression::UnitSamples;
+use crate::sample::Sample;
 
 pub trait VecTool {
     fn dot<'a, I>(&self, other:I) -> MyFp48 where I: DoubleEndedIterator<Item = &'a MyFp48>;
@@ -27,19 +28,20 @@ impl VecTool for [MyFp48] {
         self.iter().rev().map(|&a| a * a).sum()
     }
 }
-impl VecTool for std::iter::Take<std::iter::Skip<std::iter::Take<std::iter::StepBy<std::iter::Skip<std::slice::Iter<'_, u8>>>>>> {
+impl<S: Sample> VecTool for UnitSamples<'_, S> {
     fn dot<'a, I>(&self, other: I) -> MyFp48 
     where I: Iterator<Item = &'a MyFp48> {
-        self.clone().zip(other.into_iter()).map(|(&a, &b)| (MyFp48::new(a as f32) * b)).sum()
+        self.clone().zip(other).map(|(&a, &b)| MyFp48::new(a.into()) * b).sum()
     }
 
     fn sq_norm(&self) -> MyFp48 {
-        self.clone().map(|a| {let _a = MyFp48::new(*a as f32); _a*_a}).sum()
+        self.clone().map(|&a| {let _a = MyFp48::new(a.into()); _a*_a}).sum()
     }
 }
-impl VecTool for std::iter::Take<std::iter::StepBy<std::iter::Skip<std::slice::Iter<'_, MyFp48>>>> {
-    fn dot<'a, I>(&self, other: I) -> MyFp48 
-    where I: DoubleEndedIterator<Item = &'a MyFp48> {
+impl<'b, I> VecTool for std::iter::Take<std::iter::StepBy<I>>
+where I: DoubleEndedIterator<Item = &'b MyFp48> + ExactSizeIterator + Clone {
+    fn dot<'a, J>(&self, other: J) -> MyFp48 
+    where J: DoubleEndedIterator<Item = &'a MyFp48> {
         self.clone().rev().zip(other.into_iter().rev()).map(|(&a, &b)| a * b).sum()
     }
 
@@ -55,6 +57,7 @@ impl Sum for MyFp48 {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct DisplayVec<MyFp48>(pub Vec<MyFp48>);
 impl fmt::Display for DisplayVec<MyFp48> {
@@ -65,9 +68,9 @@ impl fmt::Display for DisplayVec<MyFp48> {
             .map(|item| {
                 if item.is_zero() {
                     if item.sign() == 1 {
-                        format!("0 x 2^0")
+                        "0 x 2^0".to_string()
                     } else {
-                        format!("-0 x 2^0")
+                        "-0 x 2^0".to_string()
                     }
                 } else {   
                     let mantissa = item.mantissa_and_sign();
@@ -85,10 +88,10 @@ impl fmt::Display for DisplayVec<MyFp48> {
 }
 
 pub trait HadamardProduct {
-    fn hadamard_product(&mut self, other: &Vec<MyFp48>);
+    fn hadamard_product(&mut self, other: &[MyFp48]);
 }
 impl HadamardProduct for Vec<MyFp48> {
-    fn hadamard_product(&mut self, other: &Vec<MyFp48>) {
+    fn hadamard_product(&mut self, other: &[MyFp48]) {
         self.iter_mut().zip(other.iter()).for_each(|(a, b)| *a *= *b);
     }
 }
\ No newline at end of file
@@ -0,0 +1,26 @@
+//! parallel runs independent jobs of the codec on all cores with the parallel feature, one after another without it
+//!
+//! results come back in the order of the jobs either way, so the stream doesn't depend on the feature or the cores.
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+// f of every index of 0..count, in order
+pub fn map_indices<R: Send>(count: usize, f: impl Fn(usize) -> R + Sync + Send) -> Vec<R> {
+    #[cfg(feature = "parallel")]
+    { (0..count).into_par_iter().map(f).collect() }
+    #[cfg(not(feature = "parallel"))]
+    { (0..count).map(f).collect() }
+}
+
+// f of every item, in order
+pub fn map_slice<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+    map_indices(items.len(), |i| f(&items[i]))
+}
+
+// a and b, side by side if they can be
+pub fn join<A: Send, B: Send>(a: impl FnOnce() -> A + Send, b: impl FnOnce() -> B + Send) -> (A, B) {
+    #[cfg(feature = "parallel")]
+    { rayon::join(a, b) }
+    #[cfg(not(feature = "parallel"))]
+    { (a(), b()) }
+}
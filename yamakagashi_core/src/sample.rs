@@ -0,0 +1,107 @@
+//! Sample is the type of one subpixel the codec reads and writes, u8, u16 or f32.
+//! integer samples have a known range, so coefficients are forecast from BITS,
+//! float samples can be anywhere in the wide exponent of MyFp48, so every unit records its own magnitude.
+//! Wide is a sample with a sign and one more bit, planes of a color transform are coded in it.
+use std::marker::PhantomData;
+use crate::my_float::MyFp48;
+use crate::error::{Result, YamakagashiError};
+
+pub trait Sample: Copy + Default + Into<f32> + Send + Sync + 'static {
+    const BITS: u32;
+    // full scale, white and opaque, 1.0 for float
+    const MAX: Self;
+    // bytes of a raw sample in the stream
+    const BYTES: usize = Self::BITS as usize / 8;
+    // exponent a sample is about, None if every unit stores its own
+    const MAGNITUDE: Option<i32>;
+    // levels the full scale is spread over when looking for turning points
+    const LEVELS: i64;
+    // Y, Co, Cg of this sample don't fit it, float fits as it is
+    type Wide: Sample;
+
+    // integer level of the sample for turning point search
+    fn level(self) -> i64;
+    // rounded and saturated for integers
+    fn from_f32(value: f32) -> Self;
+    // round a decoded value to the sample, saturating at 0 and max, too far out of range is CoefficientOverflow
+    fn from_my_fp48(value: MyFp48) -> Result<Self>;
+    // raw samples are big-endian
+    fn write_be(self, bytes: &mut Vec<u8>);
+    fn read_be(bytes: &[u8]) -> Self;
+}
+
+impl Sample for u8 {
+    const BITS: u32 = 8;
+    const MAX: Self = u8::MAX;
+    const MAGNITUDE: Option<i32> = Some(7);
+    const LEVELS: i64 = 1 << 8;
+    type Wide = Wide<u8>;
+
+    fn level(self) -> i64 { self as i64 }
+    fn from_f32(value: f32) -> Self { value.round() as u8 }
+    fn from_my_fp48(value: MyFp48) -> Result<Self> { value.round_u8().map_err(|_| YamakagashiError::CoefficientOverflow) }
+    fn write_be(self, bytes: &mut Vec<u8>) { bytes.push(self) }
+    fn read_be(bytes: &[u8]) -> Self { bytes[0] }
+}
+
+impl Sample for u16 {
+    const BITS: u32 = 16;
+    const MAX: Self = u16::MAX;
+    const MAGNITUDE: Option<i32> = Some(15);
+    const LEVELS: i64 = 1 << 16;
+    type Wide = Wide<u16>;
+
+    fn level(self) -> i64 { self as i64 }
+    fn from_f32(value: f32) -> Self { value.round() as u16 }
+    fn from_my_fp48(value: MyFp48) -> Result<Self> { value.round_u16().map_err(|_| YamakagashiError::CoefficientOverflow) }
+    fn write_be(self, bytes: &mut Vec<u8>) { bytes.extend(self.to_be_bytes()) }
+    fn read_be(bytes: &[u8]) -> Self { u16::from_be_bytes([bytes[0], bytes[1]]) }
+}
+
+impl Sample for f32 {
+    const BITS: u32 = 32;
+    const MAX: Self = 1.0;
+    const MAGNITUDE: Option<i32> = None;
+    // bit pattern of a float is about 2^23 * log2 of it, shifted to 2^15 levels an octave,
+    // so turning points are looked for in relative terms, dark and bright HDR alike
+    const LEVELS: i64 = 1 << 16;
+    type Wide = f32;
+
+    fn level(self) -> i64 {
+        let level = (self.to_bits() & 0x7FFF_FFFF) as i64 >> 8;
+        if self.is_sign_negative() { -level } else { level }
+    }
+    fn from_f32(value: f32) -> Self { value }
+    fn from_my_fp48(value: MyFp48) -> Result<Self> { value.round_f32().map_err(|_| YamakagashiError::CoefficientOverflow) }
+    fn write_be(self, bytes: &mut Vec<u8>) { bytes.extend(self.to_be_bytes()) }
+    fn read_be(bytes: &[u8]) -> Self { f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }
+}
+
+// integer sample S with a sign, -max..=max of S holds luma and chroma of any color transform
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Wide<S>(i32, PhantomData<S>);
+
+impl<S: Sample> Wide<S> {
+    fn new(value: i32) -> Self {
+        let max = S::LEVELS as i32 - 1;
+        Wide(value.clamp(-max, max), PhantomData)
+    }
+}
+
+impl<S> From<Wide<S>> for f32 {
+    fn from(value: Wide<S>) -> f32 { value.0 as f32 }
+}
+
+impl<S: Sample> Sample for Wide<S> {
+    const BITS: u32 = 32;
+    const MAX: Self = Wide(S::LEVELS as i32 - 1, PhantomData);
+    const MAGNITUDE: Option<i32> = match S::MAGNITUDE { Some(magnitude) => Some(magnitude + 1), None => None };
+    const LEVELS: i64 = S::LEVELS * 2;
+    type Wide = Self;
+
+    fn level(self) -> i64 { self.0 as i64 }
+    fn from_f32(value: f32) -> Self { Wide::new(value.round() as i32) }
+    fn from_my_fp48(value: MyFp48) -> Result<Self> { value.round_f32().map(Self::from_f32).map_err(|_| YamakagashiError::CoefficientOverflow) }
+    fn write_be(self, bytes: &mut Vec<u8>) { bytes.extend(self.0.to_be_bytes()) }
+    fn read_be(bytes: &[u8]) -> Self { Wide::new(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])) }
+}
@@ -0,0 +1,72 @@
+//! scan direction is the way units run through an image
+//!
+//! rows is the way every unit ran so far, columns codes the image transposed, so a unit runs up a column.
+//! vertical stripes are a few long units along columns but many short ones along rows.
+//! every coding tool is applied to the transposed image as it is, so 4:2:2 halves chroma along the scan,
+//! inter-row prediction predicts from the column on the left and patches see the rectangle transposed.
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScanDirection {
+    #[default]
+    Rows = 0,
+    Columns = 1,
+}
+
+impl ScanDirection {
+
+    // names accepted by --scan besides auto
+    pub const NAMES: [&'static str; 2] = ["rows", "columns"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rows" => Some(ScanDirection::Rows),
+            "columns" => Some(ScanDirection::Columns),
+            _ => None,
+        }
+    }
+
+    // name from_name takes
+    pub fn name(self) -> &'static str {
+        match self {
+            ScanDirection::Rows => "rows",
+            ScanDirection::Columns => "columns",
+        }
+    }
+
+    // number recorded in the header
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(ScanDirection::Rows),
+            1 => Ok(ScanDirection::Columns),
+            _ => Err(YamakagashiError::UnsupportedHeader(format!("unknown scan direction {id}"))),
+        }
+    }
+
+    // size of the image as it is coded
+    pub fn coded_size(self, size: (u32, u32)) -> (u32, u32) {
+        match self {
+            ScanDirection::Rows => size,
+            ScanDirection::Columns => (size.1, size.0),
+        }
+    }
+}
+
+// interleaved image of size with x and y swapped, doing it twice gives the image back
+pub fn transpose<S: Sample>(image: &[S], number_of_colors: u8, size: (u32, u32)) -> Vec<S> {
+
+    let (width, height, colors) = (size.0 as usize, size.1 as usize, number_of_colors as usize);
+    let mut transposed = Vec::with_capacity(image.len());
+    for x in 0..width {
+        for y in 0..height {
+            transposed.extend_from_slice(&image[(y * width + x) * colors..][..colors]);
+        }
+    }
+
+    transposed
+}
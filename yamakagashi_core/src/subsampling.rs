@@ -0,0 +1,119 @@
+//! chroma subsampling keeps the 2 chroma planes of a color transform at reduced resolution
+//!
+//! 4:2:2 halves the width, 4:2:0 halves the width and the height, a chroma sample is the mean of the 2 or 2x2
+//! samples it covers, so it sits between them. luma and alpha stay at full resolution.
+//! units are polynomials, so the decoder evaluates chroma units at full horizontal resolution
+//! instead of repeating samples, and interpolates chroma rows linearly for 4:2:0.
+use crate::color_transform::ColorTransform;
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChromaSubsampling {
+    #[default]
+    Yuv444 = 0,
+    Yuv422 = 1,
+    Yuv420 = 2,
+}
+
+impl ChromaSubsampling {
+
+    // names accepted by --subsampling
+    pub const NAMES: [&'static str; 3] = ["444", "422", "420"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "444" | "4:4:4" => Some(ChromaSubsampling::Yuv444),
+            "422" | "4:2:2" => Some(ChromaSubsampling::Yuv422),
+            "420" | "4:2:0" => Some(ChromaSubsampling::Yuv420),
+            _ => None,
+        }
+    }
+
+    // number recorded in the header
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(ChromaSubsampling::Yuv444),
+            1 => Ok(ChromaSubsampling::Yuv422),
+            2 => Ok(ChromaSubsampling::Yuv420),
+            _ => Err(YamakagashiError::UnsupportedHeader(format!("unknown chroma subsampling {id}"))),
+        }
+    }
+
+    // (horizontal, vertical) of chroma planes
+    pub fn factor(self) -> (u32, u32) {
+        match self {
+            ChromaSubsampling::Yuv444 => (1, 1),
+            ChromaSubsampling::Yuv422 => (2, 1),
+            ChromaSubsampling::Yuv420 => (2, 2),
+        }
+    }
+
+    // size of a plane, planes 1 and 2 are chroma
+    pub fn plane_size(self, size: (u32, u32), which_color: usize) -> (u32, u32) {
+        let (x, y) = if which_color == 1 || which_color == 2 { self.factor() } else { (1, 1) };
+        (size.0.div_ceil(x), size.1.div_ceil(y))
+    }
+}
+
+// only a color transform makes chroma planes
+pub fn check_subsampling(subsampling: ChromaSubsampling, color_transform: ColorTransform) -> Result<()> {
+    if subsampling != ChromaSubsampling::Yuv444 && color_transform == ColorTransform::None {
+        return Err(YamakagashiError::UnsupportedHeader("chroma subsampling needs a color transform".to_string()));
+    }
+    Ok(())
+}
+
+// one plane of interleaved image, each sample is the mean of the samples it covers
+pub fn downsample<S: Sample>(image: &[S], number_of_colors: u8, which_color: usize, size: (u32, u32), subsampling: ChromaSubsampling) -> Vec<S> {
+
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let (x_factor, y_factor) = subsampling.factor();
+    let (x_factor, y_factor) = (x_factor as usize, y_factor as usize);
+    let sample = |x: usize, y: usize| -> f32 { image[(y * width + x) * number_of_colors as usize + which_color].into() };
+
+    let mut plane = Vec::with_capacity(width.div_ceil(x_factor) * height.div_ceil(y_factor));
+    for y in (0..height).step_by(y_factor) {
+        for x in (0..width).step_by(x_factor) {
+            let (x_end, y_end) = ((x + x_factor).min(width), (y + y_factor).min(height));
+            let sum: f32 = (y..y_end).flat_map(|y| (x..x_end).map(move |x| (x, y))).map(|(x, y)| sample(x, y)).sum();
+            plane.push(S::from_f32(sum / ((x_end - x) * (y_end - y)) as f32));
+        }
+    }
+
+    plane
+}
+
+// where full resolution row y sits among chroma rows, a chroma row sits in the middle of the rows it covers
+fn chroma_position(y: u32, plane_height: u32, subsampling: ChromaSubsampling) -> f32 {
+    ((y as f32 + 0.5) / subsampling.factor().1 as f32 - 0.5).clamp(0.0, plane_height as f32 - 1.0)
+}
+
+// chroma rows full resolution rows ys are interpolated from
+pub fn upsampled_from(ys: Range<u32>, plane_height: u32, subsampling: ChromaSubsampling) -> Range<usize> {
+    let first = chroma_position(ys.start, plane_height, subsampling).floor() as usize;
+    let last = (chroma_position(ys.end - 1, plane_height, subsampling).floor() as usize + 1).min(plane_height as usize - 1);
+    first..last + 1
+}
+
+// full resolution rows ys from chroma rows which are already full width, linear between the rows they sit on
+// rows: the chroma rows of upsampled_from(ys), first_row is the first of them
+pub fn upsample_rows<S: Sample>(rows: &[Vec<S>], first_row: usize, plane_height: u32, ys: Range<u32>, subsampling: ChromaSubsampling) -> Vec<S> {
+
+    let last = plane_height as usize - 1;
+
+    let mut plane = Vec::with_capacity(rows.first().map_or(0, Vec::len) * ys.len());
+    for y in ys {
+        let position = chroma_position(y, plane_height, subsampling);
+        let (lower, fraction) = (position.floor() as usize, position.fract());
+        let (lower_row, upper_row) = (&rows[lower - first_row], &rows[(lower + 1).min(last) - first_row]);
+        plane.extend(lower_row.iter().zip(upper_row).map(|(&a, &b)| S::from_f32(Into::<f32>::into(a) * (1.0 - fraction) + Into::<f32>::into(b) * fraction)));
+    }
+
+    plane
+}
@@ -0,0 +1,91 @@
+//! tiling cuts an image into rectangles which are coded and packed by the entropy backend each on its own
+//!
+//! a tile is a whole image to the codec, with its own units, patches and scan direction,
+//! so one tile can be decoded without the others and tiles can be coded side by side.
+//! tiles run row by row from the first row of the image data, the last ones are cut short at the edges,
+//! a tile as wide as the image makes row bands.
+use crate::error::{Result, YamakagashiError};
+use crate::sample::Sample;
+use crate::scan::ScanDirection;
+
+// (x, y, width, height) in pixels of the image
+pub type TileRect = (u32, u32, u32, u32);
+
+// one tile as stored, image data of the tile coded along scan
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tile {
+    pub scan: ScanDirection,
+    pub data: Vec<u8>,
+}
+
+// an empty tile would never end
+pub fn check_tile_size(tile_size: (u32, u32)) -> Result<()> {
+    if tile_size.0 == 0 || tile_size.1 == 0 {
+        return Err(YamakagashiError::UnsupportedHeader(format!("tile size {}x{} has no pixels", tile_size.0, tile_size.1)));
+    }
+    Ok(())
+}
+
+// region has to be in the image
+pub fn check_region(region: TileRect, size: (u32, u32)) -> Result<()> {
+    let (x, y, width, height) = region;
+    if x as u64 + width as u64 > size.0 as u64 || y as u64 + height as u64 > size.1 as u64 {
+        return Err(YamakagashiError::RegionOutOfImage { region, size });
+    }
+    Ok(())
+}
+
+// number of tiles, without making their rects
+pub fn number_of_tiles(image_size: (u32, u32), tile_size: (u32, u32)) -> usize {
+    image_size.0.div_ceil(tile_size.0) as usize * image_size.1.div_ceil(tile_size.1) as usize
+}
+
+pub fn tile_rects(image_size: (u32, u32), tile_size: (u32, u32)) -> Vec<TileRect> {
+    (0..image_size.1).step_by(tile_size.1 as usize)
+        .flat_map(|y| (0..image_size.0).step_by(tile_size.0 as usize).map(move |x| (x, y, tile_size.0.min(image_size.0 - x), tile_size.1.min(image_size.1 - y))))
+        .collect()
+}
+
+// interleaved pixels of rect
+pub fn crop<S: Sample>(image: &[S], number_of_colors: u8, image_width: u32, (x, y, width, height): TileRect) -> Vec<S> {
+    let colors = number_of_colors as usize;
+    (y..y + height).flat_map(|row| {
+        let start = (row as usize * image_width as usize + x as usize) * colors;
+        image[start..start + width as usize * colors].iter().copied()
+    }).collect()
+}
+
+// put the interleaved pixels of rect back into image
+pub fn paste<S: Sample>(image: &mut [S], number_of_colors: u8, image_width: u32, (x, y, width, height): TileRect, tile: &[S]) {
+    let (colors, row_length) = (number_of_colors as usize, width as usize * number_of_colors as usize);
+    for (row, tile_row) in (y..y + height).zip(tile.chunks_exact(row_length)) {
+        let start = (row as usize * image_width as usize + x as usize) * colors;
+        image[start..start + row_length].copy_from_slice(tile_row);
+    }
+}
+
+#[test]
+fn tiling_test() {
+    // 100x70 gray of smooth waves, tiles of 32x32 are cut short at the right and the top
+    let image: Vec<u8> = (0..100 * 70).map(|i| { let (x, y) = ((i % 100) as f32, (i / 100) as f32); (128.0 + 60.0 * (x / 20.0).sin() + 40.0 * (y / 15.0).cos()) as u8 }).collect();
+    let rects = tile_rects((100, 70), (32, 32));
+    assert_eq!(rects.len(), number_of_tiles((100, 70), (32, 32)));
+    assert_eq!(rects.iter().map(|rect| rect.2 * rect.3).sum::<u32>(), 100 * 70);
+    assert_eq!(rects[3], (96, 0, 4, 32));
+
+    let options = crate::CodingOptions::default();
+    let tiles = crate::bitmap_to_yamakagashi_tiles(&image, 1, (100, 70), 90, &options, (32, 32), true).unwrap();
+    let decoded = crate::yamakagashi_tiles_to_bitmap::<u8>(&tiles, 1, (100, 70), &options, (32, 32)).unwrap();
+    // tiles lose no more than the whole image does
+    let max_difference = decoded.iter().zip(&image).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+    let whole = crate::yamakagashi_to_bitmap::<u8>(crate::bitmap_to_yamakagashi(image.clone(), 1, (100, 70), 90, &options).unwrap(), 1, (100, 70), &options).unwrap();
+    let whole_difference = whole.iter().zip(&image).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+    assert!(max_difference <= whole_difference + 4, "max difference {max_difference} against {whole_difference}");
+
+    // one tile alone is the same pixels as in the whole image
+    let tile = crate::yamakagashi_to_bitmap::<u8>(tiles[5].data.clone(), 1, (rects[5].2, rects[5].3), &crate::CodingOptions { scan: tiles[5].scan, ..options }).unwrap();
+    assert_eq!(tile, crop(&decoded, 1, 100, rects[5]));
+
+    assert!(crate::bitmap_to_yamakagashi_tiles(&image, 1, (100, 70), 90, &options, (0, 32), false).is_err());
+    assert!(crate::yamakagashi_tiles_to_bitmap::<u8>(&tiles[1..], 1, (100, 70), &options, (32, 32)).is_err());
+}